@@ -1,10 +1,20 @@
+use crate::server;
 use crate::teleport::*;
 use crate::teleport::{TeleportAction, TeleportFeatures, TeleportStatus};
-use crate::teleport::{TeleportInit, TeleportInitAck};
+use crate::teleport::{TeleportInit, TeleportInitAck, TeleportJoin};
+use crate::transport;
 use crate::utils::print_updates;
 use crate::*;
 use rndz::tcp::Client;
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader};
+use std::net::ToSocketAddrs;
+use std::os::unix::fs::FileExt;
 use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
 #[derive(Debug)]
 struct Replace {
@@ -119,12 +129,195 @@ fn find_replacements(opt: &mut Opt) -> Replace {
     rep
 }
 
+/// Dials the server, either directly, via an `rndz` rendezvous server, or
+/// (with `--ssh`) by bootstrapping a one-shot remote server over an SSH
+/// channel first.
+fn connect(opt: &Opt) -> Result<Box<dyn transport::Stream>, Error> {
+    if let Some(ref target) = opt.ssh {
+        return connect_via_ssh(target, opt);
+    }
+
+    if let Some(ref rndz_server) = opt.rndz_server {
+        let local_id = opt
+            .local_id
+            .as_ref()
+            .ok_or(Error::new(ErrorKind::InvalidInput, "local_id not set"))?;
+        let remote_id = opt
+            .remote_id
+            .as_ref()
+            .ok_or(Error::new(ErrorKind::InvalidInput, "remote_id not set"))?;
+
+        println!("rndz {}: {} -> {}", rndz_server, local_id, remote_id);
+
+        let mut c = Client::new(rndz_server, local_id, None)?;
+        let s = c.connect(remote_id)?;
+        println!(
+            "connect {} at {} success",
+            remote_id,
+            s.peer_addr().unwrap()
+        );
+        Ok(Box::new(s))
+    } else {
+        let addr = match format!("{}:{}", opt.dest, opt.port).parse::<SocketAddr>() {
+            Ok(a) => a,
+            Err(_) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Error with destination address",
+                ))
+            }
+        };
+        match transport::connect(addr, opt.transport) {
+            Ok(s) => Ok(s),
+            Err(s) => {
+                println!("Error connecting to: {}:{}", opt.dest, opt.port);
+                Err(s)
+            }
+        }
+    }
+}
+
+/// Spawns `ssh <target> <remote_bin> --one-shot ...` to bring up a server on
+/// the far end without any pre-existing listener or rendezvous server, reads
+/// its `TELEPORT CONNECT <port> <token>` announcement off the child's
+/// stdout, connects directly to that port, and sends the token as the
+/// bootstrap credential `server::run_one_shot` is waiting to read.
+fn connect_via_ssh(target: &str, opt: &Opt) -> Result<Box<dyn transport::Stream>, Error> {
+    let mut child = Command::new("ssh")
+        .arg(target)
+        .arg(&opt.remote_bin)
+        .arg("--one-shot")
+        .arg("--transport")
+        .arg(opt.transport.to_string())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let mut stdout = BufReader::new(
+        child
+            .stdout
+            .take()
+            .expect("spawned with Stdio::piped()"),
+    );
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if stdout.read_line(&mut line)? == 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "ssh session closed before announcing a port",
+            ));
+        }
+        if let Some(rest) = line.trim_end().strip_prefix("TELEPORT CONNECT ") {
+            let mut parts = rest.split(' ');
+            let port: u16 = parts
+                .next()
+                .and_then(|p| p.parse().ok())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Malformed bootstrap line"))?;
+            let token = parts
+                .next()
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Malformed bootstrap line"))?
+                .to_string();
+
+            // The ssh process is no longer needed once it has handed off to
+            // the remote server it spawned; reap it in the background so we
+            // don't leak a zombie once this function returns.
+            thread::spawn(move || {
+                let _ = child.wait();
+            });
+
+            let host = target.rsplit('@').next().unwrap_or(target);
+            let addr = (host, port)
+                .to_socket_addrs()?
+                .next()
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Could not resolve ssh target"))?;
+
+            let mut stream = transport::connect(addr, opt.transport)?;
+            stream.write_all(token.as_bytes())?;
+            return Ok(stream);
+        }
+    }
+}
+
+/// Runs the (optional) ECDH handshake over an already-connected `stream`,
+/// authenticating the server's identity against `~/.teleport/known_hosts`
+/// and, if `--cipher` was given, confirming the server honored the pin.
+/// Returns `None` when `--encrypt` wasn't requested.
+fn handshake(opt: &Opt, stream: &mut Box<dyn transport::Stream>) -> Result<Option<TeleportEnc>, Error> {
+    if !opt.encrypt {
+        return Ok(None);
+    }
+
+    // Generate EC keypair
+    let mut ctx = TeleportEnc::new();
+    // A --cipher pin replaces the default negotiable AEAD list with
+    // a single demand; the server either has it too or we bail below.
+    if let Some(cipher) = opt.cipher {
+        ctx.supported = vec![cipher];
+    }
+    let privkey = crypto::genkey(&mut ctx);
+    // Send pubkey
+    utils::send_packet(stream, TeleportAction::Ecdh, &None, None, ctx.serialize(), 0)?;
+    // Receive remote pubkey and generate session secret
+    let packet = utils::recv_packet(stream, &None, None)?;
+    if packet.action != TeleportAction::EcdhAck as u8 {
+        return Ok(None);
+    }
+
+    ctx.deserialize(&packet.data)?;
+
+    // Authenticate the handshake against ~/.teleport/known_hosts
+    // before trusting the derived session secret, so an active
+    // MITM can't just swap in its own ECDH pubkey unnoticed.
+    let remote = ctx.remote_pubkey().expect("deserialize just ran");
+    let fingerprint = match (&ctx.identity, &ctx.signature) {
+        (Some(identity), Some(signature)) => {
+            if !crypto::verify_pubkey(identity, &remote, signature) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Server identity signature does not match its ECDH pubkey",
+                ));
+            }
+            knownhosts::fingerprint(identity.as_bytes())
+        }
+        _ => knownhosts::fingerprint(remote.as_bytes()),
+    };
+    knownhosts::verify(&knownhosts::host_key(opt), &fingerprint, opt)?;
+
+    ctx.calc_secret(privkey);
+
+    if let Some(cipher) = opt.cipher {
+        if ctx.cipher != Some(cipher) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Server did not accept the requested cipher: {}", cipher),
+            ));
+        }
+    }
+
+    Ok(Some(ctx))
+}
+
 /// Client function sends filename and file data for each filepath
 pub fn run(mut opt: Opt) -> Result<(), Error> {
+    // send_parallel's worker connections are never encrypted (negotiating
+    // per-worker session keys is left for a follow-up), so silently
+    // honoring both flags together would downgrade confidentiality without
+    // telling the user. Refuse instead.
+    if opt.streams > 1 && opt.encrypt {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--streams > 1 cannot be combined with --encrypt: worker connections are not encrypted",
+        ));
+    }
+
+    // In --get pull mode the client is the receiver; hand off entirely.
+    if !opt.get.is_empty() {
+        return run_get(opt);
+    }
+
     print!("Teleporter Client {} => ", VERSION);
     let start_time = Instant::now();
-    let mut sent = 0;
-    let mut skip = 0;
 
     // Generate a list of replacement names and fix up the input list
     let rep = find_replacements(&mut opt);
@@ -138,230 +331,544 @@ pub fn run(mut opt: Opt) -> Result<(), Error> {
         return Ok(());
     }
 
-    // For each filepath in the input vector...
-    for (num, item) in files.iter().enumerate() {
-        let file_time = Instant::now();
-
-        let mut enc: Option<TeleportEnc> = None;
-
-        let filepath = item;
-        let mut filename = filepath.clone().to_string();
-
-        // Locate and replace the filename of the transfer file, if renamed
-        for (idx, item) in rep.orig.iter().enumerate() {
-            if item.contains(&filepath.to_string()) {
-                filename = rep.new[idx].clone();
+    // With --streams and more than one file, hand whole files out to a
+    // shared queue of workers so several transfer at once; a single file
+    // still gets its own N-way byte-range split inside send_one_file.
+    let (sent, skip) = if opt.streams > 1 && files.len() > 1 {
+        send_files_parallel(&opt, &rep, &files)?
+    } else {
+        let mut sent = 0;
+        let mut skip = 0;
+        for (num, item) in files.iter().enumerate() {
+            match send_one_file(&opt, &rep, num, item, files.len(), true)? {
+                FileOutcome::Sent => sent += 1,
+                FileOutcome::SameHash => skip += 1,
+                FileOutcome::Refused => {}
+                FileOutcome::Fatal => break,
             }
         }
+        (sent, skip)
+    };
 
-        // Validate file
-        let file = match File::open(&filepath) {
-            Ok(f) => f,
-            Err(s) => {
-                println!("Error opening file: {}", filepath);
-                return Err(s);
-            }
-        };
+    let total_time = start_time.elapsed();
+    println!(
+        "Teleported {}/{}/{} Sent/Same/Total in {:.2?}",
+        sent,
+        skip,
+        sent + skip,
+        total_time
+    );
+    Ok(())
+}
 
-        let thread_file = File::open(&filepath)?;
-        // Skip if opt.no_delta present, otherwise calculate the delta hash of the file
-        let handle = match opt.overwrite && !opt.no_delta {
-            true => Some(thread::spawn(move || {
-                utils::calc_delta_hash(&thread_file).unwrap()
-            })),
-            false => None,
-        };
+/// How `send_one_file` resolved a single push, used both by the sequential
+/// loop and `send_files_parallel`'s workers to drive the Sent/Same/Total
+/// summary and decide whether to keep going.
+enum FileOutcome {
+    Sent,
+    /// The server already had a copy with a matching delta hash; nothing
+    /// was sent.
+    SameHash,
+    /// The server refused this one file (no overwrite/permission/space);
+    /// the rest of the batch is still worth trying.
+    Refused,
+    /// The server rejected the whole session (wrong version, refused
+    /// encryption); no point trying more files after this.
+    Fatal,
+}
 
-        // Remove all path info if !opt.keep_path
-        if !opt.keep_path {
-            filename = Path::new(&filename)
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_string();
+/// Sends several files concurrently across up to `opt.streams` worker
+/// threads pulling file indices from a shared queue, rather than one at a
+/// time. Each worker owns one full connection and negotiates/sends a whole
+/// file before picking up its next one from the queue.
+fn send_files_parallel(opt: &Opt, rep: &Replace, files: &[String]) -> Result<(u32, u32), Error> {
+    let queue: Mutex<VecDeque<usize>> = Mutex::new((0..files.len()).collect());
+    let sent = AtomicU64::new(0);
+    let skip = AtomicU64::new(0);
+    let aborted = AtomicBool::new(false);
+    let worker_count = opt.streams.min(files.len());
+
+    thread::scope(|scope| -> Result<(), Error> {
+        let mut handles = Vec::new();
+        for _ in 0..worker_count {
+            handles.push(scope.spawn(|| -> Result<(), Error> {
+                loop {
+                    if aborted.load(Ordering::Acquire) {
+                        break;
+                    }
+                    let num = match queue.lock().unwrap().pop_front() {
+                        Some(n) => n,
+                        None => break,
+                    };
+                    match send_one_file(opt, rep, num, &files[num], files.len(), false)? {
+                        FileOutcome::Sent => {
+                            sent.fetch_add(1, Ordering::Relaxed);
+                        }
+                        FileOutcome::SameHash => {
+                            skip.fetch_add(1, Ordering::Relaxed);
+                        }
+                        FileOutcome::Refused => {}
+                        FileOutcome::Fatal => {
+                            aborted.store(true, Ordering::Release);
+                            break;
+                        }
+                    }
+                }
+                Ok(())
+            }));
         }
+        for handle in handles {
+            handle.join().expect("file worker panicked")?;
+        }
+        Ok(())
+    })?;
 
-        // Populate features
-        let meta = file.metadata()?;
-        let mut header = TeleportInit::new(TeleportFeatures::NewFile);
-        let mut features: u32 = 0;
+    Ok((
+        sent.load(Ordering::Relaxed) as u32,
+        skip.load(Ordering::Relaxed) as u32,
+    ))
+}
 
-        // Add delta flag by default
-        if !opt.no_delta {
-            features |= TeleportFeatures::Delta as u32;
-        }
+/// Negotiates and sends a single file in push mode: connects, performs the
+/// (optional) ECDH handshake, exchanges Init/InitAck, and streams the data.
+/// Shared by `run`'s sequential loop and `send_files_parallel`'s workers.
+///
+/// `allow_stream_split` gates the internal `--streams` byte-range split
+/// (`send_parallel`): it's only safe in the single-file/sequential call
+/// path. `send_files_parallel` already spreads files across up to
+/// `opt.streams` whole-connection workers, so letting each of those also
+/// split its own file across `opt.streams` connections would open up to
+/// N² connections and multiply N² copies of the chunk0-3 finalize race.
+fn send_one_file(
+    opt: &Opt,
+    rep: &Replace,
+    num: usize,
+    filepath: &str,
+    total_files: usize,
+    allow_stream_split: bool,
+) -> Result<FileOutcome, Error> {
+    let file_time = Instant::now();
 
-        // Add overwrite flag if enabled
-        if opt.overwrite {
-            features |= TeleportFeatures::Overwrite as u32;
-        }
+    let mut filename = filepath.to_string();
 
-        // Add backup flag if enabled
-        if opt.backup {
-            features |= TeleportFeatures::Backup as u32;
+    // Locate and replace the filename of the transfer file, if renamed
+    for (idx, item) in rep.orig.iter().enumerate() {
+        if item.contains(&filepath.to_string()) {
+            filename = rep.new[idx].clone();
         }
+    }
 
-        // Add rename flag if enabled
-        if opt.filename_append {
-            features |= TeleportFeatures::Rename as u32;
+    // Validate file
+    let file = match File::open(filepath) {
+        Ok(f) => f,
+        Err(s) => {
+            println!("Error opening file: {}", filepath);
+            return Err(s);
         }
-        header.features = features;
-        header.chmod = meta.permissions().mode();
-        header.filesize = meta.len();
-        header.filename = filename.chars().collect();
-
-        // Connect to server
-        let mut stream = if let Some(ref rndz_server) = opt.rndz_server {
-            let local_id = opt
-                .local_id
-                .as_ref()
-                .ok_or(Error::new(ErrorKind::InvalidInput, "local_id not set"))?;
-            let remote_id = opt
-                .remote_id
-                .as_ref()
-                .ok_or(Error::new(ErrorKind::InvalidInput, "remote_id not set"))?;
+    };
 
-            println!("rndz {}: {} -> {}", rndz_server, local_id, remote_id);
+    let thread_file = File::open(filepath)?;
+    // Skip if opt.no_delta present, otherwise calculate the delta hash of the file
+    let handle = match opt.overwrite && !opt.no_delta {
+        true => Some(thread::spawn(move || {
+            utils::calc_delta_hash(&thread_file).unwrap()
+        })),
+        false => None,
+    };
 
-            let mut c = Client::new(rndz_server, &local_id, None)?;
-            let s = c.connect(&remote_id)?;
-            println!(
-                "connect {} at {} success",
-                remote_id,
-                s.peer_addr().unwrap()
-            );
-            s
-        } else {
-            let addr = format!("{}:{}", opt.dest, opt.port);
-            match TcpStream::connect(match addr.parse::<SocketAddr>() {
-                Ok(a) => a,
-                Err(_) => {
-                    return Err(Error::new(
-                        ErrorKind::InvalidData,
-                        "Error with destination address",
-                    ))
-                }
-            }) {
-                Ok(s) => s,
-                Err(s) => {
-                    println!("Error connecting to: {}:{}", opt.dest, opt.port);
-                    return Err(s);
-                }
-            }
-        };
+    // Remove all path info if !opt.keep_path
+    if !opt.keep_path {
+        filename = Path::new(&filename)
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+    }
 
-        // If encrypt is enabled
-        if opt.encrypt {
-            // Generate EC keypair
-            let mut ctx = TeleportEnc::new();
-            let privkey = crypto::genkey(&mut ctx);
-            // Send pubkey
-            utils::send_packet(&mut stream, TeleportAction::Ecdh, &None, ctx.serialize())?;
-            // Receive remote pubkey and generate session secret
-            let packet = utils::recv_packet(&mut stream, &None)?;
-            if packet.action == TeleportAction::EcdhAck as u8 {
-                ctx.deserialize(&packet.data)?;
-                ctx.calc_secret(privkey);
-                enc = Some(ctx);
-            }
-        }
+    // Populate features
+    let meta = file.metadata()?;
+    let mut header = TeleportInit::new(TeleportFeatures::NewFile);
+    let mut features: u32 = 0;
+
+    // Add delta flag by default
+    if !opt.no_delta {
+        features |= TeleportFeatures::Delta as u32;
+    }
+
+    // Add overwrite flag if enabled
+    if opt.overwrite {
+        features |= TeleportFeatures::Overwrite as u32;
+    }
+
+    // Add backup flag if enabled
+    if opt.backup {
+        features |= TeleportFeatures::Backup as u32;
+    }
+
+    // Add rename flag if enabled
+    if opt.filename_append {
+        features |= TeleportFeatures::Rename as u32;
+    }
+
+    // Add compress flag if enabled, advertising our threshold
+    if opt.compress {
+        features |= TeleportFeatures::Compress as u32;
+    }
+    header.features = features;
+    header.chmod = meta.permissions().mode();
+    header.filesize = meta.len();
+    header.filename = filename.chars().collect();
+    header.compress_threshold = opt.compress_threshold;
+    header.stream_id = utils::random_id(7);
+    header.stream_count = if allow_stream_split { opt.streams.max(1) } else { 1 } as u32;
+    // Hashed up front (rather than only at completion, like the
+    // trailing TeleportData::file_hash) so a --daemon server can tell
+    // a resumed attempt at this transfer apart from a stale .part file
+    // left by sending a different file under the same destination name.
+    // This is a separate, cheap whole-file read (not the chunked delta
+    // hash), so it's computed unconditionally - --no-delta must still skip
+    // calc_delta_hash, but a resume with no hash at all to validate against
+    // isn't safe to trust, so it's never skipped here.
+    header.source_hash = Some(utils::whole_file_hash(&file, meta.len())?);
+
+    // Connect to server
+    let mut stream = connect(opt)?;
 
-        // Send header first
-        utils::send_packet(&mut stream, TeleportAction::Init, &enc, header.serialize()?)?;
+    // If encrypt is enabled, perform the ECDH handshake
+    let enc = handshake(opt, &mut stream)?;
 
-        // Receive response from server
-        let packet = utils::recv_packet(&mut stream, &enc)?;
-        let mut recv = TeleportInitAck::new(TeleportStatus::UnknownAction);
-        recv.deserialize(&packet.data)?;
+    // Send header first
+    utils::send_packet(&mut stream, TeleportAction::Init, &enc, None, header.serialize()?, 0)?;
 
-        if num == 0 {
+    // Receive response from server
+    let packet = utils::recv_packet(&mut stream, &enc, None)?;
+    let mut recv = TeleportInitAck::new(TeleportStatus::UnknownAction);
+    recv.deserialize(&packet.data)?;
+
+    if num == 0 {
+        println!(
+            "Server {}.{}.{}",
+            recv.version[0], recv.version[1], recv.version[2]
+        );
+    }
+
+    // Validate response
+    match recv.status.try_into().unwrap() {
+        TeleportStatus::NoOverwrite => {
+            println!("The server refused to overwrite the file: {:?}", &filename);
+            return Ok(FileOutcome::Refused);
+        }
+        TeleportStatus::NoPermission => {
             println!(
-                "Server {}.{}.{}",
-                recv.version[0], recv.version[1], recv.version[2]
+                "The server does not have permission to write to this file: {:?}",
+                &filename
             );
+            return Ok(FileOutcome::Refused);
+        }
+        TeleportStatus::NoSpace => {
+            println!(
+                "The server has no space available to write the file: {:?}",
+                &filename
+            );
+            return Ok(FileOutcome::Refused);
+        }
+        TeleportStatus::WrongVersion => {
+            println!(
+                "Version mismatch! Server: {:?} Us: {}",
+                recv.version, VERSION
+            );
+            return Ok(FileOutcome::Fatal);
+        }
+        TeleportStatus::RequiresEncryption => {
+            println!("The server requires encryption");
+            return Ok(FileOutcome::Fatal);
+        }
+        TeleportStatus::EncryptionError => {
+            println!("Error initializing encryption handshake");
+            return Ok(FileOutcome::Fatal);
         }
+        _ => (),
+    };
 
-        // Validate response
-        match recv.status.try_into().unwrap() {
-            TeleportStatus::NoOverwrite => {
-                println!("The server refused to overwrite the file: {:?}", &filename);
-                continue;
-            }
-            TeleportStatus::NoPermission => {
-                println!(
-                    "The server does not have permission to write to this file: {:?}",
-                    &filename
-                );
-                continue;
-            }
-            TeleportStatus::NoSpace => {
-                println!(
-                    "The server has no space available to write the file: {:?}",
-                    &filename
-                );
-                continue;
-            }
-            TeleportStatus::WrongVersion => {
-                println!(
-                    "Version mismatch! Server: {:?} Us: {}",
-                    recv.version, VERSION
-                );
-                break;
-            }
-            TeleportStatus::RequiresEncryption => {
-                println!("The server requires encryption");
-                break;
-            }
-            TeleportStatus::EncryptionError => {
-                println!("Error initializing encryption handshake");
-                break;
-            }
-            _ => (),
-        };
+    // If TeleportDelta was received, else None
+    let csum_recv = recv.delta.as_ref().map(|r| r.hash);
+    let mut file_delta: Option<TeleportDelta> = None;
+    if utils::check_feature(&recv.features, TeleportFeatures::Overwrite) {
+        file_delta = handle.map(|s| s.join().expect("calc_file_hash panicked"));
+    }
+
+    // Only compress data chunks if the server also accepted the feature
+    let compress_threshold = if opt.compress && utils::check_feature(&recv.features, TeleportFeatures::Compress) {
+        Some(header.compress_threshold)
+    } else {
+        None
+    };
+
+    println!("Sending file {}/{}: {}", num + 1, total_files, &filename);
 
-        // If TeleportDelta was received, else None
-        let csum_recv = recv.delta.as_ref().map(|r| r.hash);
-        let mut file_delta: Option<TeleportDelta> = None;
-        if utils::check_feature(&recv.features, TeleportFeatures::Overwrite) {
-            file_delta = handle.map(|s| s.join().expect("calc_file_hash panicked"));
+    let outcome;
+    if csum_recv.is_some()
+        && file_delta.is_some()
+        && file_delta.as_ref().unwrap().hash == csum_recv.unwrap()
+    {
+        // File matches hash
+        send_data_complete(&mut stream, &enc, compress_threshold, file, Some(csum_recv.unwrap()))?;
+        outcome = FileOutcome::SameHash;
+    } else if allow_stream_split && opt.streams > 1 && stream.try_clone_tcp().is_some() {
+        // Split the remaining data across several parallel connections. Each
+        // worker dials a fresh connection and resends its whole range from
+        // scratch, so any resume offset or delta match the server found is
+        // lost - let the user know rather than silently paying for a full
+        // resend.
+        if recv.resume_offset.is_some() || file_delta.is_some() {
+            println!("--streams > 1 re-sends the full file over fresh connections; ignoring resume/delta match");
         }
+        let primary = stream.try_clone_tcp().expect("just checked Some above");
+        send_parallel(opt, primary, file, &header, compress_threshold)?;
+        outcome = FileOutcome::Sent;
+    } else {
+        if allow_stream_split && opt.streams > 1 {
+            println!("--streams > 1 requires --transport tcp; sending over a single connection instead");
+        }
+
+        // Resume a previously interrupted transfer of this same
+        // destination, if the server found a matching `.part` file.
+        let resume_offset = recv.resume_offset.unwrap_or(0);
+
+        // Send file data
+        send(
+            &mut stream,
+            file,
+            &header,
+            &enc,
+            compress_threshold,
+            recv.delta,
+            file_delta,
+            resume_offset,
+        )?;
+        outcome = FileOutcome::Sent;
+    }
+
+    // Print file transfer statistics
+    let duration = file_time.elapsed();
+    let speed = (header.filesize as f64 * 8.0) / duration.as_secs() as f64 / 1024.0 / 1024.0;
+    println!(" done! Time: {:.2?} Speed: {:.3} Mbps", duration, speed);
+
+    Ok(outcome)
+}
+
+/// Client function for `--get`: connects once, lists the remote paths it
+/// wants, then receives each file the server streams back in turn over the
+/// same connection. The mirror image of `run`'s push loop, with the client
+/// now playing the receiver's role that `server::recv` normally plays.
+fn run_get(opt: Opt) -> Result<(), Error> {
+    print!("Teleporter Client {} => ", VERSION);
+    let start_time = Instant::now();
+
+    let mut stream = connect(&opt)?;
+    let enc = handshake(&opt, &mut stream)?;
+
+    let req = TeleportGetRequest {
+        paths: opt.get.clone(),
+    };
+    utils::send_packet(&mut stream, TeleportAction::Get, &enc, None, req.serialize()?, 0)?;
+
+    let mut received = 0u32;
+    loop {
+        let packet = utils::recv_packet(&mut stream, &enc, None)?;
+        let mut header = TeleportInit::new(TeleportFeatures::NewFile);
+        header.deserialize(&packet.data)?;
 
-        println!("Sending file {}/{}: {}", num + 1, files.len(), &filename);
-
-        if csum_recv.is_some()
-            && file_delta.is_some()
-            && file_delta.as_ref().unwrap().hash == csum_recv.unwrap()
-        {
-            // File matches hash
-            send_data_complete(stream, &enc, file)?;
-            skip += 1;
-        } else {
-            // Send file data
-            send(stream, file, &header, &enc, recv.delta, file_delta)?;
-            sent += 1;
+        if header.totalfiles == 0 {
+            println!(" => Server has no matching files.");
+            break;
         }
 
-        // Print file transfer statistics
-        let duration = file_time.elapsed();
-        let speed = (header.filesize as f64 * 8.0) / duration.as_secs() as f64 / 1024.0 / 1024.0;
-        println!(" done! Time: {:.2?} Speed: {:.3} Mbps", duration, speed);
+        recv_get_file(&mut stream, &enc, &opt, &header)?;
+        received += 1;
+
+        if header.filenum >= header.totalfiles {
+            break;
+        }
     }
+
     let total_time = start_time.elapsed();
+    println!("Teleported {} file(s) in {:.2?}", received, total_time);
+    Ok(())
+}
+
+/// Receives one file the server is streaming back in `--get` pull mode,
+/// the mirror image of `server::recv_data`: validates the destination path,
+/// applies `--overwrite`/`--backup`/`--filename-append` locally, offers a
+/// delta of any existing local copy, then writes `Data`/`Copy` chunks to a
+/// `.part` file until the sender signals completion.
+fn recv_get_file(
+    stream: &mut dyn transport::Stream,
+    enc: &Option<TeleportEnc>,
+    opt: &Opt,
+    header: &TeleportInit,
+) -> Result<(), Error> {
+    let filename: String = header.filename.iter().collect();
     println!(
-        "Teleported {}/{}/{} Sent/Same/Total in {:.2?}",
-        sent,
-        skip,
-        sent + skip,
-        total_time
+        "Receiving file {}/{}: {:?}",
+        header.filenum, header.totalfiles, filename
     );
+
+    let path = match server::resolve_path(&filename, opt) {
+        Ok(p) => p,
+        Err(e) => {
+            let ack = TeleportInitAck::new(TeleportStatus::NoPermission);
+            utils::send_packet(stream, TeleportAction::InitAck, enc, None, ack.serialize()?, 0)?;
+            return Err(e);
+        }
+    };
+
+    if path.exists() && !opt.overwrite {
+        println!(" => already exists locally, skipping (use --overwrite to replace it)");
+        let ack = TeleportInitAck::new(TeleportStatus::NoOverwrite);
+        utils::send_packet(stream, TeleportAction::InitAck, enc, None, ack.serialize()?, 0)?;
+        return Ok(());
+    }
+
+    // If the destination exists and we were asked to overwrite it, hand
+    // back our delta hash so the server can send a rolling-checksum diff
+    // instead of the whole file, and keep the existing contents open so
+    // any `TeleportAction::Copy` instructions referencing them can be
+    // served.
+    let mut ack_features: u32 = 0;
+    let mut ack = TeleportInitAck::new(TeleportStatus::Proceed);
+    let mut source: Option<File> = None;
+    if opt.overwrite && path.exists() {
+        let existing = File::open(&path)?;
+        ack.delta = Some(utils::calc_delta_hash(&existing)?);
+        ack_features |= TeleportFeatures::Overwrite as u32;
+        source = Some(existing);
+    }
+
+    // Only agree to compress data chunks if we were asked to ourselves
+    let compress_requested = utils::check_feature(&Some(header.features), TeleportFeatures::Compress);
+    let compress_threshold = if opt.compress && compress_requested {
+        ack_features |= TeleportFeatures::Compress as u32;
+        Some(opt.compress_threshold)
+    } else {
+        None
+    };
+    ack.features = Some(ack_features);
+
+    // Stage the new contents to a `.part` file next to the destination, the
+    // same as a push transfer does on the server side: it protects `source`
+    // while Copy instructions are still being served from it, and leaves a
+    // resumable partial transfer behind if the connection drops.
+    let dest_path = server::make_room(&path, opt)?;
+    let tmp_path = PathBuf::from(format!("{}.part", dest_path.display()));
+    let chunk_size = ack.delta.as_ref().map(|d| d.chunk_size).unwrap_or(0);
+
+    let mut resume_offset = tmp_path.metadata().map(|m| m.len()).unwrap_or(0);
+    if resume_offset > header.filesize {
+        resume_offset = 0;
+    }
+    // Only trust a `.part` left by a previous attempt if its
+    // `.teleport-partial` sidecar's source hash matches this sender's -
+    // otherwise it's a stale leftover from fetching a different file under
+    // this same name, and we start over instead of stitching the two
+    // together.
+    if resume_offset > 0 {
+        // As in server::recv: a missing sidecar or a sender hash of None
+        // means there's nothing to validate the resume against, so don't
+        // trust file size alone.
+        let matches = server::resume_matches(server::read_partial_state(&dest_path).as_ref(), header.source_hash);
+        if !matches {
+            resume_offset = 0;
+        }
+    }
+    ack.resume_offset = if resume_offset > 0 {
+        Some(resume_offset)
+    } else {
+        None
+    };
+
+    server::write_partial_state(
+        &dest_path,
+        &server::PartialState {
+            source_hash: header.source_hash,
+            received: resume_offset,
+        },
+    )?;
+
+    utils::send_packet(stream, TeleportAction::InitAck, enc, None, ack.serialize()?, 0)?;
+
+    let file = OpenOptions::new().create(true).write(true).open(&tmp_path)?;
+    let mut perms = file.metadata()?.permissions();
+    perms.set_mode(header.chmod);
+    fs::set_permissions(&tmp_path, perms)?;
+
+    let mut final_hash: Option<u64> = None;
+    loop {
+        let packet = utils::recv_packet(stream, enc, compress_threshold)?;
+
+        if packet.action == TeleportAction::Copy as u8 {
+            let mut copy = TeleportCopy {
+                dest_offset: 0,
+                block_index: 0,
+                len: 0,
+            };
+            copy.deserialize(&packet.data)?;
+
+            let src = source
+                .as_ref()
+                .expect("received a Copy instruction without a source file");
+            utils::apply_copy(src, chunk_size, &copy, &file)?;
+            print_updates((copy.dest_offset + copy.len as u64) as f64, header);
+            continue;
+        }
+
+        let mut chunk = TeleportData {
+            offset: 0,
+            data_len: 0,
+            data: Vec::new(),
+            file_hash: None,
+        };
+        chunk.deserialize(&packet.data)?;
+
+        if chunk.data_len == 0 {
+            final_hash = chunk.file_hash;
+            break;
+        }
+
+        file.write_at(&chunk.data, chunk.offset)?;
+        print_updates((chunk.offset + chunk.data_len as u64) as f64, header);
+    }
+
+    file.set_len(header.filesize)?;
+
+    if let Some(expected) = final_hash {
+        let actual = utils::whole_file_hash(&file, header.filesize)?;
+        if actual != expected {
+            println!(
+                " FAILED: hash mismatch after transfer; keeping {:?} for a future resume",
+                tmp_path
+            );
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Whole-file hash verification failed",
+            ));
+        }
+    }
+
+    fs::rename(&tmp_path, &dest_path)?;
+    server::remove_partial_state(&dest_path);
+    println!(" done!");
+
     Ok(())
 }
 
-fn send_data_complete(
-    mut stream: TcpStream,
+pub(crate) fn send_data_complete(
+    stream: &mut dyn transport::Stream,
     enc: &Option<TeleportEnc>,
+    compress_threshold: Option<u32>,
     file: File,
+    file_hash: Option<u64>,
 ) -> Result<(), Error> {
     let meta = file.metadata()?;
 
@@ -369,59 +876,57 @@ fn send_data_complete(
         offset: meta.len() as u64,
         data_len: 0,
         data: Vec::<u8>::new(),
+        file_hash,
     };
 
     // Send the data chunk
-    utils::send_packet(&mut stream, TeleportAction::Data, enc, chunk.serialize()?)?;
+    utils::send_packet(
+        stream,
+        TeleportAction::Data,
+        enc,
+        compress_threshold,
+        chunk.serialize()?,
+        chunk.offset,
+    )?;
 
     Ok(())
 }
 
-/// Send function receives the ACK for data and sends the file data
-fn send(
-    mut stream: TcpStream,
+/// Send function receives the ACK for data and sends the file data. If the
+/// server handed back a `TeleportDelta` of its existing file and we were
+/// able to compute our own, a rolling-checksum diff (see the `delta`
+/// module) is used so that only the bytes the server doesn't already have
+/// are resent, regardless of where in the file they moved to. Otherwise the
+/// whole file is sent sequentially.
+pub(crate) fn send(
+    stream: &mut dyn transport::Stream,
     mut file: File,
     header: &TeleportInit,
     enc: &Option<TeleportEnc>,
+    compress_threshold: Option<u32>,
     delta: Option<TeleportDelta>,
     file_delta: Option<TeleportDelta>,
+    resume_offset: u64,
 ) -> Result<(), Error> {
-    let mut buf = Vec::<u8>::new();
-    // Set transfer chunk size to delta chunk size, or default to 4096
-    match delta {
-        Some(ref d) => buf.resize(d.chunk_size as usize, 0),
-        None => buf.resize(4096, 0),
+    // Our own whole-file hash, used to let the receiver verify the finished
+    // file (resumed or not) before renaming its `.part` into place. The
+    // delta hash doubles as this when we computed one; otherwise (e.g.
+    // --no-delta) fall back to header.source_hash, which is always
+    // computed regardless of --no-delta for exactly this purpose.
+    let file_hash = file_delta.as_ref().map(|d| d.hash).or(header.source_hash);
+
+    if let (Some(remote), Some(_)) = (delta.as_ref(), file_delta.as_ref()) {
+        return send_delta(stream, file, header, enc, compress_threshold, remote, file_hash);
     }
 
-    // If present, get the lengths of the delta hash arrays
-    let compare_delta = delta.is_some() && file_delta.is_some();
-    let delta_len = if delta.is_some() {
-        delta.as_ref().unwrap().chunk_hash.len()
-    } else {
-        0
-    };
-    let file_delta_len = if file_delta.is_some() {
-        file_delta.as_ref().unwrap().chunk_hash.len()
-    } else {
-        0
-    };
+    let mut buf = Vec::<u8>::new();
+    buf.resize(4096, 0);
 
-    // Send file data
-    let mut sent = 0;
+    // Skip straight to the bytes the server doesn't already have from a
+    // previous, interrupted attempt at this same transfer.
+    file.seek(SeekFrom::Start(resume_offset))?;
+    let mut sent = resume_offset as usize;
     loop {
-        // Check if hash matches, if so: skip chunk
-        let index = sent / buf.len();
-        if compare_delta
-            && index < delta_len
-            && index < file_delta_len
-            && delta.as_ref().unwrap().chunk_hash[index]
-                == file_delta.as_ref().unwrap().chunk_hash[index]
-        {
-            sent += buf.len();
-            continue;
-        }
-
-        file.seek(SeekFrom::Start(sent as u64))?;
         // Read a chunk of the file
         let len = match file.read(&mut buf) {
             Ok(l) => l,
@@ -438,16 +943,197 @@ fn send(
             offset: sent as u64,
             data_len: len as u32,
             data: data.to_vec(),
+            file_hash: None,
         };
 
         // Send the data chunk
-        utils::send_packet(&mut stream, TeleportAction::Data, enc, chunk.serialize()?)?;
+        utils::send_packet(
+            stream,
+            TeleportAction::Data,
+            enc,
+            compress_threshold,
+            chunk.serialize()?,
+            chunk.offset,
+        )?;
 
         sent += len;
         print_updates(sent as f64, header);
     }
 
-    send_data_complete(stream, enc, file)?;
+    send_data_complete(stream, enc, compress_threshold, file, file_hash)?;
+
+    Ok(())
+}
+
+/// Diffs our copy of the file against `remote` (the server's existing
+/// copy's delta hash) with a sliding rolling checksum, then replays the
+/// result as a sequence of `TeleportData` (LITERAL) and `TeleportCopy`
+/// (COPY, reusing one of the server's own existing blocks) packets.
+///
+/// This reads the whole file into memory up front, since the matching
+/// window needs to compare against every block of `remote` regardless of
+/// where it ends up in our copy; that tradeoff is acceptable since this
+/// path only runs for `--overwrite` transfers of files small enough that a
+/// delta is worth computing in the first place.
+pub(crate) fn send_delta(
+    stream: &mut dyn transport::Stream,
+    mut file: File,
+    header: &TeleportInit,
+    enc: &Option<TeleportEnc>,
+    compress_threshold: Option<u32>,
+    remote: &TeleportDelta,
+    file_hash: Option<u64>,
+) -> Result<(), Error> {
+    let mut data = Vec::<u8>::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut data)?;
+
+    let mut offset = 0u64;
+    for op in delta::compute_ops(&data, remote) {
+        match op {
+            delta::DeltaOp::Copy { block_index, len } => {
+                let copy = TeleportCopy {
+                    dest_offset: offset,
+                    block_index,
+                    len,
+                };
+                utils::send_packet(
+                    stream,
+                    TeleportAction::Copy,
+                    enc,
+                    compress_threshold,
+                    copy.serialize()?,
+                    copy.dest_offset,
+                )?;
+                offset += len as u64;
+            }
+            delta::DeltaOp::Literal(bytes) => {
+                let mut pos = 0;
+                while pos < bytes.len() {
+                    let len = std::cmp::min(bytes.len() - pos, 1 << 20);
+                    let chunk = TeleportData {
+                        offset,
+                        data_len: len as u32,
+                        data: bytes[pos..pos + len].to_vec(),
+                        file_hash: None,
+                    };
+                    utils::send_packet(
+                        stream,
+                        TeleportAction::Data,
+                        enc,
+                        compress_threshold,
+                        chunk.serialize()?,
+                        chunk.offset,
+                    )?;
+                    offset += len as u64;
+                    pos += len;
+                }
+            }
+        }
+        print_updates(offset as f64, header);
+    }
+
+    send_data_complete(stream, enc, compress_threshold, file, file_hash)?;
+
+    Ok(())
+}
+
+/// Splits the file's data across `opt.streams` parallel connections, each
+/// pulling chunk offsets from a shared work queue and writing them to the
+/// server independently via `TeleportAction::Data`. `primary` has already
+/// completed the Init/InitAck handshake and becomes worker 0; the remaining
+/// workers dial a fresh connection and attach with `TeleportAction::Join`.
+///
+/// Encryption is intentionally not applied to the data connections here:
+/// negotiating per-worker session keys is left for a follow-up, so `run`
+/// refuses `--streams` together with `--encrypt` outright rather than
+/// silently sending plaintext.
+fn send_parallel(
+    opt: &Opt,
+    primary: TcpStream,
+    file: File,
+    header: &TeleportInit,
+    compress_threshold: Option<u32>,
+) -> Result<(), Error> {
+    const WORK_CHUNK: u64 = 1 << 20;
+
+    let filesize = header.filesize;
+    let queue: VecDeque<u64> = (0..filesize).step_by(WORK_CHUNK as usize).collect();
+    let queue = Arc::new(Mutex::new(queue));
+    let sent = Arc::new(AtomicU64::new(0));
+
+    let mut handles = Vec::new();
+    for i in 0..opt.streams {
+        let queue = Arc::clone(&queue);
+        let sent = Arc::clone(&sent);
+        let file = file.try_clone()?;
+        let header = header.clone();
+        let addr = format!("{}:{}", opt.dest, opt.port);
+        let stream_id = header.stream_id.clone();
+
+        // Worker 0 reuses the already-connected, already-negotiated stream.
+        let primary = if i == 0 { Some(primary.try_clone()?) } else { None };
+        // Only the primary connection negotiated compression with the server.
+        let threshold = if i == 0 { compress_threshold } else { None };
+
+        handles.push(thread::spawn(move || -> Result<(), Error> {
+            let mut stream = match primary {
+                Some(s) => s,
+                None => {
+                    let mut s = TcpStream::connect(addr.parse::<SocketAddr>().map_err(|_| {
+                        Error::new(ErrorKind::InvalidData, "Error with destination address")
+                    })?)?;
+                    let join = TeleportJoin { stream_id };
+                    utils::send_packet(&mut s, TeleportAction::Join, &None, None, join.serialize()?, 0)?;
+                    utils::recv_packet(&mut s, &None, None)?;
+                    s
+                }
+            };
+
+            let mut buf = vec![0u8; WORK_CHUNK as usize];
+            loop {
+                let offset = match queue.lock().unwrap().pop_front() {
+                    Some(o) => o,
+                    None => break,
+                };
+                let len = std::cmp::min(WORK_CHUNK, filesize - offset) as usize;
+                let n = file.read_at(&mut buf[..len], offset)?;
+                if n == 0 {
+                    continue;
+                }
+
+                let chunk = TeleportData {
+                    offset,
+                    data_len: n as u32,
+                    data: buf[..n].to_vec(),
+                    file_hash: None,
+                };
+                utils::send_packet(&mut stream, TeleportAction::Data, &None, threshold, chunk.serialize()?, 0)?;
+
+                let total = sent.fetch_add(n as u64, Ordering::Relaxed) + n as u64;
+                print_updates(total as f64, &header);
+            }
+
+            // Each worker's connection is served by its own `recv_data` loop
+            // on the server side, so each one needs its own terminator - the
+            // server finalizes once all `stream_count` connections have
+            // reported in. Whole-file verification isn't wired up for
+            // parallel transfers yet, so no file_hash is sent.
+            let done = TeleportData {
+                offset: filesize,
+                data_len: 0,
+                data: Vec::new(),
+                file_hash: None,
+            };
+            utils::send_packet(&mut stream, TeleportAction::Data, &None, threshold, done.serialize()?, 0)?;
+
+            Ok(())
+        }));
+    }
+
+    for handle in handles {
+        handle.join().expect("stream worker panicked")?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,54 @@
+use crate::teleport::TeleportEnc;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+/// Generates an ephemeral X25519 keypair for the ECDH handshake, stashes
+/// the public half in `ctx` (to be sent to the peer via `TeleportEnc::serialize`),
+/// and returns the private half so the caller can feed it into `calc_secret`
+/// once the peer's pubkey has arrived.
+pub fn genkey(ctx: &mut TeleportEnc) -> EphemeralSecret {
+    let privkey = EphemeralSecret::random_from_rng(rand::rngs::OsRng);
+    let pubkey = PublicKey::from(&privkey);
+    ctx.pubkey = Some(pubkey);
+    privkey
+}
+
+/// Loads the server's long-lived Ed25519 identity key from `path`,
+/// generating and persisting a new one (mode 0600) if it doesn't exist yet.
+/// Used to sign the ephemeral ECDH pubkey offered in each handshake so
+/// clients can pin a stable fingerprint across sessions.
+pub fn load_or_create_identity(path: &Path) -> Result<SigningKey, Error> {
+    if let Ok(bytes) = fs::read(path) {
+        let raw: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Corrupt identity key file"))?;
+        return Ok(SigningKey::from_bytes(&raw));
+    }
+
+    let identity = SigningKey::generate(&mut rand::rngs::OsRng);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, identity.to_bytes())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+
+    Ok(identity)
+}
+
+/// Signs `ephemeral`'s raw bytes with the server's long-lived identity key.
+pub fn sign_pubkey(identity: &SigningKey, ephemeral: &PublicKey) -> Signature {
+    identity.sign(ephemeral.as_bytes())
+}
+
+/// Verifies that `signature` over `ephemeral`'s raw bytes was produced by
+/// `identity`'s private key.
+pub fn verify_pubkey(identity: &VerifyingKey, ephemeral: &PublicKey, signature: &Signature) -> bool {
+    identity.verify(ephemeral.as_bytes(), signature).is_ok()
+}
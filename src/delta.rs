@@ -0,0 +1,202 @@
+use crate::teleport::TeleportDelta;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use xxhash_rust::xxh3;
+
+const MOD_ADLER: u32 = 65521;
+
+/// Adler-32-style rolling checksum over a fixed-size window, allowing an
+/// O(1) update (`roll`) as the window slides forward one byte at a time,
+/// instead of recomputing the whole window's checksum from scratch.
+pub struct RollingChecksum {
+    s1: u32,
+    s2: u32,
+    window: usize,
+}
+
+impl RollingChecksum {
+    pub fn new(data: &[u8]) -> Self {
+        let mut s1: u32 = 0;
+        let mut s2: u32 = 0;
+        for (i, &b) in data.iter().enumerate() {
+            s1 = (s1 + b as u32) % MOD_ADLER;
+            s2 = (s2 + (data.len() - i) as u32 * b as u32) % MOD_ADLER;
+        }
+        RollingChecksum {
+            s1,
+            s2,
+            window: data.len(),
+        }
+    }
+
+    pub fn digest(&self) -> u32 {
+        self.s1 | (self.s2 << 16)
+    }
+
+    /// Slides the window forward by one byte: `out` is the byte leaving the
+    /// window, `inn` is the byte entering it.
+    pub fn roll(&mut self, out: u8, inn: u8) {
+        let n = self.window as i64;
+        let s1 = (self.s1 as i64 - out as i64 + inn as i64).rem_euclid(MOD_ADLER as i64);
+        let s2 = (self.s2 as i64 - n * out as i64 + s1).rem_euclid(MOD_ADLER as i64);
+        self.s1 = s1 as u32;
+        self.s2 = s2 as u32;
+    }
+}
+
+fn strong_hash(data: &[u8]) -> u64 {
+    let mut hasher = xxh3::Xxh3::new();
+    hasher.write(data);
+    hasher.finish()
+}
+
+/// A single instruction for reconstructing the file being sent on top of
+/// the receiver's existing copy of it.
+pub enum DeltaOp {
+    /// Reuse `len` bytes the receiver already has, starting at
+    /// `block_index * TeleportDelta::chunk_size` in its existing file.
+    Copy { block_index: u32, len: u32 },
+    /// Bytes the receiver doesn't already have and must be sent in full.
+    Literal(Vec<u8>),
+}
+
+/// Slides a byte-by-byte window over `data`, matching it against `remote`'s
+/// per-block weak+strong checksums of the receiver's existing file, and
+/// returns the sequence of COPY/LITERAL operations needed to turn that file
+/// into `data`. Unlike a fixed-offset chunk comparison, this finds matching
+/// blocks regardless of where bytes were inserted or deleted earlier in the
+/// file.
+pub fn compute_ops(data: &[u8], remote: &TeleportDelta) -> Vec<DeltaOp> {
+    let block_size = remote.chunk_size as usize;
+    // remote arrives over the wire via serde_json with no validation of its
+    // own; a malformed or hostile peer could send mismatched weak_hash/
+    // chunk_hash lengths, which would otherwise panic on the chunk_hash
+    // index below. Fall back to a full literal rather than trust it.
+    if block_size == 0 || data.len() < block_size || remote.chunk_hash.len() != remote.weak_hash.len() {
+        return vec![DeltaOp::Literal(data.to_vec())];
+    }
+
+    let mut by_weak: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (i, &w) in remote.weak_hash.iter().enumerate() {
+        by_weak.entry(w).or_default().push(i as u32);
+    }
+
+    let mut ops = Vec::new();
+    let mut literal = Vec::new();
+    let mut pos = 0usize;
+    let mut roll = RollingChecksum::new(&data[pos..pos + block_size]);
+
+    while pos + block_size <= data.len() {
+        let window = &data[pos..pos + block_size];
+        let matched = by_weak.get(&roll.digest()).and_then(|candidates| {
+            let strong = strong_hash(window);
+            candidates
+                .iter()
+                .find(|&&idx| remote.chunk_hash[idx as usize] == strong)
+                .copied()
+        });
+
+        if let Some(block_index) = matched {
+            if !literal.is_empty() {
+                ops.push(DeltaOp::Literal(std::mem::take(&mut literal)));
+            }
+            ops.push(DeltaOp::Copy {
+                block_index,
+                len: block_size as u32,
+            });
+            pos += block_size;
+            if pos + block_size <= data.len() {
+                roll = RollingChecksum::new(&data[pos..pos + block_size]);
+            }
+        } else {
+            literal.push(data[pos]);
+            pos += 1;
+            if pos + block_size <= data.len() {
+                roll.roll(data[pos - 1], data[pos + block_size - 1]);
+            }
+        }
+    }
+
+    // A trailing run shorter than a full block can never match one.
+    literal.extend_from_slice(&data[pos..]);
+    if !literal.is_empty() {
+        ops.push(DeltaOp::Literal(literal));
+    }
+
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote_delta(data: &[u8], chunk_size: u32) -> TeleportDelta {
+        let mut delta = TeleportDelta::new();
+        delta.filesize = data.len() as u64;
+        delta.chunk_size = chunk_size;
+        for chunk in data.chunks(chunk_size as usize) {
+            delta.chunk_hash.push(strong_hash(chunk));
+            delta.weak_hash.push(RollingChecksum::new(chunk).digest());
+        }
+        delta
+    }
+
+    fn apply_ops(ops: &[DeltaOp], remote_data: &[u8], chunk_size: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        for op in ops {
+            match op {
+                DeltaOp::Copy { block_index, len } => {
+                    let start = *block_index as usize * chunk_size as usize;
+                    out.extend_from_slice(&remote_data[start..start + *len as usize]);
+                }
+                DeltaOp::Literal(bytes) => out.extend_from_slice(bytes),
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn identical_data_is_all_copies() {
+        let data = b"abcdefghijklmnop".to_vec();
+        let remote = remote_delta(&data, 4);
+        let ops = compute_ops(&data, &remote);
+
+        assert!(ops.iter().all(|op| matches!(op, DeltaOp::Copy { .. })));
+        assert_eq!(apply_ops(&ops, &data, 4), data);
+    }
+
+    #[test]
+    fn insertion_shifts_matches_but_still_reconstructs() {
+        let remote_data = b"aaaabbbbccccdddd".to_vec();
+        let remote = remote_delta(&remote_data, 4);
+
+        // Insert a few bytes before the matching blocks, so none of them
+        // land on the original fixed-offset boundaries.
+        let mut data = b"XYZ".to_vec();
+        data.extend_from_slice(&remote_data);
+
+        let ops = compute_ops(&data, &remote);
+        assert!(ops.iter().any(|op| matches!(op, DeltaOp::Copy { .. })));
+        assert_eq!(apply_ops(&ops, &remote_data, 4), data);
+    }
+
+    #[test]
+    fn no_match_is_all_literal() {
+        let remote = remote_delta(b"aaaabbbbccccdddd", 4);
+        let data = b"wholly different content".to_vec();
+
+        let ops = compute_ops(&data, &remote);
+        assert!(ops.iter().all(|op| matches!(op, DeltaOp::Literal(_))));
+        assert_eq!(apply_ops(&ops, b"", 4), data);
+    }
+
+    #[test]
+    fn data_shorter_than_block_size_is_literal() {
+        let remote = remote_delta(b"aaaabbbbccccdddd", 4);
+        let data = b"ab".to_vec();
+
+        let ops = compute_ops(&data, &remote);
+        assert_eq!(ops.len(), 1);
+        assert!(matches!(&ops[0], DeltaOp::Literal(bytes) if bytes == &data));
+    }
+}
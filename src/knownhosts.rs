@@ -0,0 +1,144 @@
+use crate::Opt;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, BufRead, Error, ErrorKind, Write};
+use std::path::PathBuf;
+
+/// `~/.teleport/known_hosts`, SSH-style: one `<host> <fingerprint>` pair
+/// per line.
+fn known_hosts_path() -> Result<PathBuf, Error> {
+    Ok(teleport_dir()?.join("known_hosts"))
+}
+
+/// `~/.teleport/identity`, the server's default long-lived identity key
+/// when `--identity-key` isn't given.
+pub fn default_identity_path() -> Result<PathBuf, Error> {
+    Ok(teleport_dir()?.join("identity"))
+}
+
+/// `~/.teleport/teleport.pid`, the default pidfile location for `--daemon`
+/// when `--pidfile` isn't given.
+pub fn default_pidfile_path() -> Result<PathBuf, Error> {
+    Ok(teleport_dir()?.join("teleport.pid"))
+}
+
+/// `~/.teleport/teleport.log`, where a `--daemon` server's stdio is
+/// redirected once it detaches from its controlling terminal.
+pub fn default_log_path() -> Result<PathBuf, Error> {
+    Ok(teleport_dir()?.join("teleport.log"))
+}
+
+pub(crate) fn teleport_dir() -> Result<PathBuf, Error> {
+    let home = std::env::var("HOME")
+        .map_err(|_| Error::new(ErrorKind::NotFound, "HOME is not set"))?;
+    let dir = PathBuf::from(home).join(".teleport");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// The key a server's pinned fingerprint is stored under: its rndz
+/// `remote_id` when rendezvous is in use, otherwise `dest:port`.
+pub fn host_key(opt: &Opt) -> String {
+    match &opt.remote_id {
+        Some(id) => id.clone(),
+        None => format!("{}:{}", opt.dest, opt.port),
+    }
+}
+
+/// An SSH-style fingerprint of a raw public key: `SHA256:<base64, unpadded>`.
+pub fn fingerprint(pubkey: &[u8]) -> String {
+    format!("SHA256:{}", STANDARD_NO_PAD.encode(Sha256::digest(pubkey)))
+}
+
+fn lookup(host: &str) -> Result<Option<String>, Error> {
+    let path = known_hosts_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    for line in io::BufReader::new(fs::File::open(path)?).lines() {
+        let line = line?;
+        if let Some((h, fp)) = line.split_once(' ') {
+            if h == host {
+                return Ok(Some(fp.to_string()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn persist(host: &str, fingerprint: &str) -> Result<(), Error> {
+    let path = known_hosts_path()?;
+    let prefix = format!("{} ", host);
+    let mut lines: Vec<String> = if path.exists() {
+        fs::read_to_string(&path)?
+            .lines()
+            .filter(|l| !l.starts_with(&prefix))
+            .map(String::from)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    lines.push(format!("{}{}", prefix, fingerprint));
+    fs::write(path, lines.join("\n") + "\n")
+}
+
+/// Authenticates `fingerprint` for `host` against `~/.teleport/known_hosts`,
+/// per `Opt`'s `--fingerprint`/`--trust-on-first-use` flags:
+///
+/// - `--fingerprint <fp>` pins an expected value up front and never
+///   consults or updates known_hosts; a mismatch is a hard error.
+/// - otherwise, an existing known_hosts entry must match exactly, or the
+///   connection is refused with a loud warning (possible MITM).
+/// - on first contact, `--trust-on-first-use` accepts and pins silently;
+///   without it, the user is prompted on stdin/stdout.
+pub fn verify(host: &str, fingerprint: &str, opt: &Opt) -> Result<(), Error> {
+    if let Some(expected) = &opt.fingerprint {
+        return if expected == fingerprint {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Server fingerprint {} does not match --fingerprint {}",
+                    fingerprint, expected
+                ),
+            ))
+        };
+    }
+
+    match lookup(host)? {
+        Some(known) if known == fingerprint => Ok(()),
+        Some(known) => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "WARNING: REMOTE HOST IDENTIFICATION HAS CHANGED!\n\
+                 {} is known with fingerprint {}, but it just offered {}.\n\
+                 This may mean someone is intercepting this connection. Refusing to continue.\n\
+                 Remove the matching line from ~/.teleport/known_hosts if this change is expected.",
+                host, known, fingerprint
+            ),
+        )),
+        None if opt.trust_on_first_use => persist(host, fingerprint),
+        None => {
+            print!(
+                "The authenticity of host '{}' can't be established.\n\
+                 Fingerprint: {}\n\
+                 Are you sure you want to continue connecting (yes/no)? ",
+                host, fingerprint
+            );
+            io::stdout().flush()?;
+            let mut answer = String::new();
+            io::stdin().read_line(&mut answer)?;
+            if answer.trim().eq_ignore_ascii_case("yes") {
+                persist(host, fingerprint)
+            } else {
+                Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    "Host key verification failed",
+                ))
+            }
+        }
+    }
+}
@@ -3,8 +3,7 @@ use std::fs::File;
 use std::io::{self, Read, Write};
 use std::io::{Error, ErrorKind};
 use std::io::{Seek, SeekFrom};
-use std::net::Ipv4Addr;
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::net::{SocketAddr, TcpStream};
 use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
 use std::result::Result;
@@ -15,10 +14,16 @@ use structopt::StructOpt;
 
 mod client;
 mod crypto;
+mod delta;
+mod knownhosts;
 mod server;
 mod teleport;
+mod transport;
 mod utils;
 
+use teleport::TeleportCipher;
+use transport::Transport;
+
 /// Teleporter is a simple application for sending files from Point A to Point B
 
 #[derive(Clone, Debug, StructOpt)]
@@ -27,6 +32,11 @@ pub struct Opt {
     #[structopt(short, long, parse(from_os_str), default_value = "")]
     input: Vec<PathBuf>,
 
+    /// Remote paths to fetch from the server instead of sending files to
+    /// it (client only); mutually exclusive with --input
+    #[structopt(long)]
+    get: Vec<String>,
+
     /// Destination teleporter IP address
     #[structopt(short, long, default_value = "127.0.0.1")]
     dest: String,
@@ -64,7 +74,7 @@ pub struct Opt {
     #[structopt(short, long)]
     keep_path: bool,
 
-    /// Allow absolute and relative file paths for transfers (server only) [WARNING: potentially dangerous option, use at your own risk!]
+    /// Allow absolute and relative file paths for transfers [WARNING: potentially dangerous option, use at your own risk!]
     #[structopt(long)]
     allow_dangerous_filepath: bool,
 
@@ -79,6 +89,78 @@ pub struct Opt {
     /// Require encryption for incoming connections to the server
     #[structopt(short, long)]
     must_encrypt: bool,
+
+    /// Compress data chunks larger than --compress-threshold before sending (requires both ends to opt in)
+    #[structopt(short = "z", long)]
+    compress: bool,
+
+    /// Minimum chunk size, in bytes, before compression kicks in
+    #[structopt(long, default_value = "512")]
+    compress_threshold: u32,
+
+    /// Number of parallel connections to split a transfer's data across
+    /// (client only, TCP transport only). Mutually exclusive with
+    /// --encrypt: the extra connections are never encrypted.
+    #[structopt(short = "n", long, default_value = "1")]
+    streams: usize,
+
+    /// Socket transport to carry the transfer over. `udp` uses a reliable,
+    /// LEDBAT-style congestion-controlled datagram transport instead of TCP,
+    /// which can do much better on high-bandwidth, high-latency links where
+    /// TCP's loss-based congestion control stalls.
+    #[structopt(long, default_value = "tcp")]
+    transport: Transport,
+
+    /// On first connection to an unknown server, pin its identity fingerprint
+    /// to ~/.teleport/known_hosts instead of prompting (client only)
+    #[structopt(long)]
+    trust_on_first_use: bool,
+
+    /// Only proceed if the server's identity fingerprint matches exactly;
+    /// does not consult or update ~/.teleport/known_hosts (client only)
+    #[structopt(long)]
+    fingerprint: Option<String>,
+
+    /// Path to this server's long-lived identity key, used to sign its ECDH
+    /// pubkey so clients can pin and detect rotation of it (server only)
+    /// [default: ~/.teleport/identity]
+    #[structopt(long, parse(from_os_str))]
+    identity_key: Option<PathBuf>,
+
+    /// Pin the session cipher to one of these CTR keystream ciphers instead
+    /// of letting ECDH negotiate an AEAD cipher. Requires --encrypt; the
+    /// client aborts the handshake if the server does not agree to the choice.
+    /// [possible values: chacha20, chacha8, aes128-ctr, aes192-ctr, aes256-ctr]
+    #[structopt(long)]
+    cipher: Option<TeleportCipher>,
+
+    /// SSH target (user@host) to bootstrap a one-shot remote server on
+    /// over an SSH channel instead of connecting to an already-running
+    /// server (client only)
+    #[structopt(long)]
+    ssh: Option<String>,
+
+    /// Path to the `teleport` binary on the remote host, used with --ssh
+    /// (client only)
+    #[structopt(long, default_value = "teleport")]
+    remote_bin: String,
+
+    /// Serve a single connection, announcing the bound port and a one-time
+    /// token as `TELEPORT CONNECT <port> <token>` on stdout, then exit once
+    /// that transfer completes. Set automatically on the remote end of
+    /// --ssh; not normally passed by hand.
+    #[structopt(long)]
+    one_shot: bool,
+
+    /// Detach and run as a background service (fork, setsid, redirect
+    /// stdio to ~/.teleport/teleport.log, write a pidfile) instead of
+    /// running in the foreground (server only)
+    #[structopt(long)]
+    daemon: bool,
+
+    /// Pidfile path to use with --daemon [default: ~/.teleport/teleport.pid]
+    #[structopt(long, parse(from_os_str))]
+    pidfile: Option<PathBuf>,
 }
 
 const PROTOCOL: u64 = 0x54524f50454c4554;
@@ -93,10 +175,11 @@ fn main() {
     let opt = Opt::from_args();
     let out;
 
-    // If the input filepath list is empty, assume we're in server mode
-    if opt.input.len() == 1 && opt.input[0].to_str().unwrap() == "" {
+    // If the input filepath list is empty and no --get paths were given,
+    // assume we're in server mode
+    if opt.get.is_empty() && opt.input.len() == 1 && opt.input[0].to_str().unwrap() == "" {
         out = server::run(opt);
-    // Else, we have files to send so we're in client mode
+    // Else, we have files to send or fetch so we're in client mode
     } else {
         out = client::run(opt);
     }
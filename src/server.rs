@@ -1,77 +1,805 @@
+use crate::client::{send, send_data_complete};
+use crate::teleport::*;
+use crate::transport;
 use crate::utils::print_updates;
 use crate::*;
+use daemonize::Daemonize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
+use std::fs::OpenOptions;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// A transfer in progress across one or more `--streams` worker connections,
+/// keyed by `TeleportInit::stream_id`. The file is shared via positioned
+/// (`pwrite`-style) writes, so no lock is needed around it.
+struct StreamEntry {
+    stream_id: String,
+    file: File,
+    filesize: u64,
+    remaining_workers: AtomicUsize,
+    /// The destination's previous contents, kept open read-only so
+    /// `TeleportAction::Copy` instructions can be served from it. `Some`
+    /// only when a delta was offered, i.e. the destination already existed
+    /// and overwrite was requested.
+    source: Option<File>,
+    /// Block size of `source`'s `TeleportDelta`, used to turn a
+    /// `TeleportCopy::block_index` into a byte offset into `source`.
+    chunk_size: u32,
+    /// Final destination path.
+    dest_path: PathBuf,
+    /// Staging path `file` is actually being written to (`dest_path` with a
+    /// `.part` suffix), renamed onto `dest_path` once the transfer
+    /// completes and, if the sender provided one, its whole-file hash has
+    /// been verified.
+    tmp_path: PathBuf,
+    /// The sender's `TeleportInit::source_hash`, persisted alongside
+    /// `tmp_path`'s progress so a SIGINT/SIGTERM flush commits a
+    /// `PartialState` a later resume attempt can actually match against.
+    source_hash: Option<u64>,
+}
+
+fn stream_registry() -> &'static Mutex<HashMap<String, Arc<StreamEntry>>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<String, Arc<StreamEntry>>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Sidecar persisted next to a `.part` file as `<dest_path>.teleport-partial`,
+/// recording enough about the transfer that produced it to tell a genuine
+/// resume apart from a stale `.part` left by sending a different file under
+/// the same destination name. Shared with `client::recv_get_file`, which
+/// applies the same resume-validation policy to a `--get` pull's local writes.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct PartialState {
+    pub(crate) source_hash: Option<u64>,
+    pub(crate) received: u64,
+}
+
+fn partial_state_path(dest_path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.teleport-partial", dest_path.display()))
+}
+
+pub(crate) fn read_partial_state(dest_path: &Path) -> Option<PartialState> {
+    let data = fs::read(partial_state_path(dest_path)).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+pub(crate) fn write_partial_state(dest_path: &Path, state: &PartialState) -> Result<(), Error> {
+    let data = serde_json::to_vec(state).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    fs::write(partial_state_path(dest_path), data)
+}
+
+pub(crate) fn remove_partial_state(dest_path: &Path) {
+    let _ = fs::remove_file(partial_state_path(dest_path));
+}
+
+/// Whether a `.part` file's sidecar `state` can be trusted to resume a
+/// transfer whose sender reports `source_hash`. A missing sidecar or a
+/// sender hash of `None` (e.g. `--no-delta`) means there's nothing to
+/// validate a resume against, so both sides must have a hash and they must
+/// agree - otherwise the `.part` is treated as a stale leftover from
+/// transferring a different file under this same name.
+pub(crate) fn resume_matches(state: Option<&PartialState>, source_hash: Option<u64>) -> bool {
+    match state {
+        Some(state) => state.source_hash.is_some() && state.source_hash == source_hash,
+        None => false,
+    }
+}
+
+/// Forks into the background, detaching from the controlling terminal and
+/// redirecting stdio to `~/.teleport/teleport.log`, for `--daemon`.
+fn daemonize(opt: &Opt) -> Result<(), Error> {
+    let pidfile = match &opt.pidfile {
+        Some(p) => p.clone(),
+        None => knownhosts::default_pidfile_path()?,
+    };
+    let log_path = knownhosts::default_log_path()?;
+    let stdout = File::create(&log_path)?;
+    let stderr = stdout.try_clone()?;
+
+    Daemonize::new()
+        .pid_file(pidfile)
+        .working_directory(std::env::current_dir()?)
+        .stdout(stdout)
+        .stderr(stderr)
+        .start()
+        .map_err(|e| Error::new(ErrorKind::Other, format!("Failed to daemonize: {}", e)))
+}
+
+/// Installs a SIGINT/SIGTERM handler (for `--daemon`) that flushes every
+/// in-flight transfer's data to disk and commits its `PartialState` sidecar
+/// before exiting, so a resumed attempt afterwards picks up exactly where
+/// this one left off instead of racing a half-written `.part` file.
+fn install_shutdown_handler() -> Result<(), Error> {
+    ctrlc::set_handler(move || {
+        for entry in stream_registry().lock().unwrap().values() {
+            let _ = entry.file.sync_all();
+            let received = entry.file.metadata().map(|m| m.len()).unwrap_or(0);
+            let _ = write_partial_state(
+                &entry.dest_path,
+                &PartialState {
+                    source_hash: entry.source_hash,
+                    received,
+                },
+            );
+        }
+        println!("Shutting down.");
+        std::process::exit(0);
+    })
+    .map_err(|e| Error::new(ErrorKind::Other, format!("Failed to install signal handler: {}", e)))
+}
 
 /// Server function sets up a listening socket for any incoming connnections
-pub fn run(opt: Opt) -> Result<()> {
-    // Bind to all interfaces on specified Port
-    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, opt.port)))
-        .expect(&format!("Error binding to port: {:?}", opt.port));
+pub fn run(mut opt: Opt) -> Result<(), Error> {
+    // A one-shot server (spawned over --ssh) always asks the OS for an
+    // ephemeral port rather than trusting --port, since the whole point is
+    // to avoid requiring the caller to agree on a port ahead of time.
+    if opt.one_shot {
+        opt.port = 0;
+    }
+
+    // --one-shot prints its bootstrap line on stdout for the --ssh parent
+    // to read; forking and redirecting stdio away would break that, so the
+    // two are mutually exclusive in practice and --daemon is skipped here.
+    if opt.daemon && !opt.one_shot {
+        daemonize(&opt)?;
+    }
+
+    let listener = transport::Listener::bind(&opt)
+        .unwrap_or_else(|_| panic!("Error binding to port: {:?}", opt.port));
+
+    if opt.one_shot {
+        return run_one_shot(listener, opt);
+    }
+
+    if opt.daemon {
+        install_shutdown_handler()?;
+    }
+
+    println!(
+        "Teleporter Server {} listening on port: {} ({})",
+        VERSION, opt.port, opt.transport
+    );
 
     // Listen for incoming connections
-    for stream in listener.incoming() {
+    loop {
+        let stream = listener.accept()?;
+        let opt = opt.clone();
         // Receive connections in recv function
         thread::spawn(move || {
-            recv(stream.unwrap()).unwrap();
+            if let Err(e) = recv(stream, opt) {
+                println!("Error: {}", e);
+            }
         });
     }
+}
 
-    Ok(())
+/// Serves exactly one connection then returns, announcing the ephemeral
+/// port and a one-time bootstrap token on stdout as `TELEPORT CONNECT
+/// <port> <token>` so `client::connect_via_ssh` (the far end of a --ssh
+/// session spawning us) can find and authenticate to us without a
+/// long-running listener or rendezvous server.
+fn run_one_shot(listener: transport::Listener, opt: Opt) -> Result<(), Error> {
+    let port = listener.local_addr()?.port();
+    let token = utils::random_id(32);
+
+    println!("TELEPORT CONNECT {} {}", port, token);
+    io::stdout().flush()?;
+
+    // The data connection is a raw socket the client dials directly, not one
+    // tunneled through the --ssh channel that printed this port - so a port
+    // scanner, an unrelated client, or an attacker racing the real one can
+    // just as easily be first to connect. Accept in its own thread and check
+    // each connection's token on another, so one bad or silent connection
+    // can't consume the only accept and starve the legitimate client out.
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || loop {
+        let stream = match listener.accept() {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        let token = token.clone();
+        let tx = tx.clone();
+        thread::spawn(move || {
+            let mut stream = stream;
+            let mut buf = vec![0u8; token.len()];
+            if stream.read_exact(&mut buf).is_ok() && buf == token.as_bytes() {
+                let _ = tx.send(stream);
+            }
+        });
+    });
+
+    let stream = rx
+        .recv()
+        .map_err(|_| Error::new(ErrorKind::Other, "bootstrap accept loop exited"))?;
+
+    recv(stream, opt)
+}
+
+/// Validates that the requested destination path is safe to write to unless
+/// `--allow-dangerous-filepath` was passed. Shared with `client::recv_get_file`,
+/// which applies the same policy to paths a `--get` pull writes locally.
+pub(crate) fn resolve_path(filename: &str, opt: &Opt) -> Result<PathBuf, Error> {
+    let path = Path::new(filename);
+    if !opt.allow_dangerous_filepath && (path.is_absolute() || filename.contains("..")) {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "Refusing a dangerous filepath (use --allow-dangerous-filepath to override)",
+        ));
+    }
+    Ok(path.to_path_buf())
+}
+
+/// Applies `--backup`/`--filename-append` semantics to an existing destination,
+/// returning the path the incoming data should actually be written to. Shared
+/// with `client::recv_get_file` for the same reason as `resolve_path` above.
+pub(crate) fn make_room(path: &Path, opt: &Opt) -> Result<PathBuf, Error> {
+    if !path.exists() {
+        return Ok(path.to_path_buf());
+    }
+
+    if opt.backup {
+        let bak = PathBuf::from(format!("{}.bak", path.display()));
+        fs::rename(path, bak)?;
+        return Ok(path.to_path_buf());
+    }
+
+    if opt.filename_append {
+        let mut n = 1;
+        loop {
+            let candidate = PathBuf::from(format!("{}.{}", path.display(), n));
+            if !candidate.exists() {
+                return Ok(candidate);
+            }
+            n += 1;
+        }
+    }
+
+    Ok(path.to_path_buf())
 }
 
-/// Recv receives filenames and file data for a file
-fn recv(mut stream: TcpStream) -> Result<()> {
+/// Recv receives the (optional) ECDH handshake, then the filename and file
+/// data for a single file.
+fn recv(mut stream: Box<dyn transport::Stream>, opt: Opt) -> Result<(), Error> {
     let ip = stream.peer_addr().unwrap();
+    let mut enc: Option<TeleportEnc> = None;
+
+    // The first packet is either an Ecdh handshake or the Init header.
+    let mut packet = utils::recv_packet(&mut stream, &None, None)?;
+
+    if packet.action & !(TeleportAction::Encrypted as u8) == TeleportAction::Ecdh as u8 {
+        let mut ctx = TeleportEnc::new();
+        let privkey = crypto::genkey(&mut ctx);
+        ctx.deserialize(&packet.data)?;
+        ctx.calc_secret(privkey);
+
+        // Sign our ephemeral pubkey with our long-lived identity key, if we
+        // have one, so the client can pin a fingerprint that survives us
+        // generating a fresh ECDH keypair on every connection.
+        let identity_path = match &opt.identity_key {
+            Some(p) => Some(p.clone()),
+            None => knownhosts::default_identity_path().ok(),
+        };
+        if let Some(identity_path) = identity_path {
+            let identity = crypto::load_or_create_identity(&identity_path)?;
+            let pubkey = ctx.pubkey.expect("genkey just ran");
+            ctx.identity = Some(identity.verifying_key());
+            ctx.signature = Some(crypto::sign_pubkey(&identity, &pubkey));
+        }
+
+        utils::send_packet(&mut stream, TeleportAction::EcdhAck, &None, None, ctx.serialize(), 0)?;
+        enc = Some(ctx);
+        packet = utils::recv_packet(&mut stream, &enc, None)?;
+    } else if opt.must_encrypt {
+        let ack = TeleportInitAck::new(TeleportStatus::RequiresEncryption);
+        utils::send_packet(&mut stream, TeleportAction::InitAck, &None, None, ack.serialize()?, 0)?;
+        return Ok(());
+    }
+
+    // A --streams worker attaches to an already-negotiated transfer instead
+    // of negotiating its own.
+    if packet.action & !(TeleportAction::Encrypted as u8) == TeleportAction::Join as u8 {
+        return recv_join(&mut stream, &enc, &packet.data);
+    }
+
+    // A --get pull request: we become the sender instead of the receiver.
+    if packet.action & !(TeleportAction::Encrypted as u8) == TeleportAction::Get as u8 {
+        return send_requested(&mut stream, &mut enc, &opt, &packet.data, ip);
+    }
+
+    let mut header = TeleportInit::new(TeleportFeatures::NewFile);
+    header.deserialize(&packet.data)?;
+    let filename: String = header.filename.iter().collect();
 
-    // Receive header first
-    let mut name_buf: [u8; 4096] = [0; 4096];
-    let len = stream.read(&mut name_buf)?;
-    let fix = &name_buf[..len];
-    let header: TeleportInit =
-        serde_json::from_str(str::from_utf8(&fix).unwrap()).expect("Cannot understand filename");
     println!(
         "Receiving file {}/{}: {:?} (from {})",
-        header.filenum, header.totalfiles, header.filename, ip
+        header.filenum, header.totalfiles, filename, ip
     );
 
-    // Open file for writing
-    let mut file = File::create(&header.filename).expect("Could not open file");
-    let meta = file.metadata().expect("Could not read file metadata");
-    let mut perms = meta.permissions();
+    let path = match resolve_path(&filename, &opt) {
+        Ok(p) => p,
+        Err(e) => {
+            let ack = TeleportInitAck::new(TeleportStatus::NoPermission);
+            utils::send_packet(&mut stream, TeleportAction::InitAck, &enc, None, ack.serialize()?, 0)?;
+            return Err(e);
+        }
+    };
+
+    let overwrite_requested = utils::check_feature(&Some(header.features), TeleportFeatures::Overwrite);
+    if path.exists() && !overwrite_requested {
+        let ack = TeleportInitAck::new(TeleportStatus::NoOverwrite);
+        utils::send_packet(&mut stream, TeleportAction::InitAck, &enc, None, ack.serialize()?, 0)?;
+        return Ok(());
+    }
+
+    // If the destination exists and overwrite was requested, hand back its
+    // delta hash so the client can send a rolling-checksum diff instead of
+    // the whole file, and keep the existing contents open so any
+    // `TeleportAction::Copy` instructions referencing them can be served.
+    let mut ack_features: u32 = 0;
+    let mut ack = TeleportInitAck::new(TeleportStatus::Proceed);
+    let mut source: Option<File> = None;
+    if overwrite_requested && path.exists() {
+        let existing = File::open(&path)?;
+        ack.delta = Some(utils::calc_delta_hash(&existing)?);
+        ack_features |= TeleportFeatures::Overwrite as u32;
+        source = Some(existing);
+    }
+
+    // Only agree to compress data chunks if we were asked to ourselves
+    let compress_requested = utils::check_feature(&Some(header.features), TeleportFeatures::Compress);
+    if opt.compress && compress_requested {
+        ack_features |= TeleportFeatures::Compress as u32;
+    }
+    ack.features = Some(ack_features);
+
+    // The new contents are always staged to a `.part` file next to the
+    // destination rather than written in place: this both protects the old
+    // contents `source` reads Copy instructions from, and leaves a
+    // resumable partial transfer behind if the connection drops. A `.part`
+    // already there from a previous attempt is resumed from, as long as it
+    // isn't larger than the file we're about to receive and its
+    // `.teleport-partial` sidecar's source hash matches this sender's -
+    // otherwise it's a stale leftover from sending a different file under
+    // this same name, and we start over rather than stitch the two together.
+    let dest_path = make_room(&path, &opt)?;
+    let tmp_path = PathBuf::from(format!("{}.part", dest_path.display()));
+    let chunk_size = ack.delta.as_ref().map(|d| d.chunk_size).unwrap_or(0);
+
+    let mut resume_offset = tmp_path.metadata().map(|m| m.len()).unwrap_or(0);
+    if resume_offset > header.filesize {
+        resume_offset = 0;
+    }
+    if resume_offset > 0 {
+        // Only ever resume when we can actually verify the `.part` file is a
+        // prefix of this same sender's content - a missing sidecar or a
+        // sender that sent no hash at all (e.g. --no-delta) means there's
+        // nothing to validate against, so don't gamble on file size alone.
+        let matches = resume_matches(read_partial_state(&dest_path).as_ref(), header.source_hash);
+        if !matches {
+            resume_offset = 0;
+        }
+    }
+    ack.resume_offset = if resume_offset > 0 {
+        Some(resume_offset)
+    } else {
+        None
+    };
+
+    write_partial_state(
+        &dest_path,
+        &PartialState {
+            source_hash: header.source_hash,
+            received: resume_offset,
+        },
+    )?;
+
+    let compress_threshold = if opt.compress && compress_requested {
+        Some(header.compress_threshold)
+    } else {
+        None
+    };
+
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(&tmp_path)?;
+    let mut perms = file.metadata()?.permissions();
     perms.set_mode(header.chmod);
-    fs::set_permissions(&header.filename, perms).expect("Could not set file permissions");
+    fs::set_permissions(&tmp_path, perms)?;
+
+    // Register this transfer, so that any additional --streams workers can
+    // find the same open file by stream_id, *before* acking Proceed - the
+    // client dials worker connections the instant it sees that ack, and a
+    // Join reaching recv_join before this insert would fail with "Unknown
+    // stream_id".
+    let entry = Arc::new(StreamEntry {
+        stream_id: header.stream_id.clone(),
+        file,
+        filesize: header.filesize,
+        remaining_workers: AtomicUsize::new(header.stream_count.max(1) as usize),
+        source,
+        chunk_size,
+        dest_path,
+        tmp_path,
+        source_hash: header.source_hash,
+    });
+    stream_registry()
+        .lock()
+        .unwrap()
+        .insert(header.stream_id.clone(), Arc::clone(&entry));
 
-    // Send ready for data ACK
-    let resp = TeleportResponse {
-        ack: TeleportStatus::Proceed,
+    utils::send_packet(&mut stream, TeleportAction::InitAck, &enc, None, ack.serialize()?, 0)?;
+
+    recv_data(&mut stream, &enc, compress_threshold, &entry, &header)?;
+
+    Ok(())
+}
+
+/// Attaches an additional `--streams` worker connection to the transfer
+/// `join.stream_id` names, acking Proceed and then receiving its share of
+/// `TeleportData` chunks the same way the primary connection does.
+fn recv_join(stream: &mut dyn transport::Stream, enc: &Option<TeleportEnc>, data: &[u8]) -> Result<(), Error> {
+    let mut join = TeleportJoin {
+        stream_id: String::new(),
     };
-    let serial_resp = serde_json::to_string(&resp).unwrap();
-    stream
-        .write(&serial_resp.as_bytes())
-        .expect("Failed to write to stream");
-
-    // Receive file data
-    let mut buf: [u8; 4096] = [0; 4096];
-    let mut received: u64 = 0;
+    join.deserialize(data)?;
+
+    let entry = match stream_registry().lock().unwrap().get(&join.stream_id) {
+        Some(e) => Arc::clone(e),
+        None => {
+            let ack = TeleportInitAck::new(TeleportStatus::UnknownAction);
+            utils::send_packet(stream, TeleportAction::InitAck, enc, None, ack.serialize()?, 0)?;
+            return Err(Error::new(ErrorKind::NotFound, "Unknown stream_id"));
+        }
+    };
+
+    let ack = TeleportInitAck::new(TeleportStatus::Proceed);
+    utils::send_packet(stream, TeleportAction::InitAck, enc, None, ack.serialize()?, 0)?;
+
+    // Workers don't negotiate their own compression threshold; only the
+    // primary connection's threshold applies, and it is never used for the
+    // unencrypted worker connections `send_parallel` opens.
+    let mut header = TeleportInit::new(TeleportFeatures::NewFile);
+    header.filesize = entry.filesize;
+    recv_data(stream, enc, None, &entry, &header)
+}
+
+/// Receives `TeleportData`/`TeleportCopy` chunks until this connection's
+/// sender signals completion with a zero-length `TeleportData` chunk,
+/// writing each at its absolute offset via a positioned (`pwrite`-style)
+/// write. When the last of `stream_count` workers finishes, the file is
+/// truncated to its final size, verified against the sender's whole-file
+/// hash if it sent one, and renamed from its `.part` path into place.
+fn recv_data(
+    stream: &mut dyn transport::Stream,
+    enc: &Option<TeleportEnc>,
+    compress_threshold: Option<u32>,
+    entry: &Arc<StreamEntry>,
+    header: &TeleportInit,
+) -> Result<(), Error> {
+    let mut final_hash: Option<u64> = None;
+
     loop {
-        // Read from network connection
-        let len = stream.read(&mut buf).expect("Failed to read stream");
-        if len == 0 {
-            println!(" done!");
-            break;
+        let packet = utils::recv_packet(stream, enc, compress_threshold)?;
+
+        if packet.action == TeleportAction::Copy as u8 {
+            let mut copy = TeleportCopy {
+                dest_offset: 0,
+                block_index: 0,
+                len: 0,
+            };
+            copy.deserialize(&packet.data)?;
+
+            let source = entry
+                .source
+                .as_ref()
+                .expect("received a Copy instruction without a source file");
+            utils::apply_copy(source, entry.chunk_size, &copy, &entry.file)?;
+            print_updates((copy.dest_offset + copy.len as u64) as f64, header);
+            continue;
         }
-        let data = &buf[..len];
 
-        // Write received data to file
-        let wrote = file.write(&data).expect("Failed to write to file");
-        if len != wrote {
-            println!("Error writing to file: {} (read: {}, wrote: {}", &header.filename, len, wrote);
+        let mut chunk = TeleportData {
+            offset: 0,
+            data_len: 0,
+            data: Vec::new(),
+            file_hash: None,
+        };
+        chunk.deserialize(&packet.data)?;
+
+        if chunk.data_len == 0 {
+            final_hash = chunk.file_hash;
             break;
         }
 
-        received += len as u64;
-        print_updates(received as f64, &header);
+        entry.file.write_at(&chunk.data, chunk.offset)?;
+        print_updates((chunk.offset + chunk.data_len as u64) as f64, header);
+    }
+
+    if entry.remaining_workers.fetch_sub(1, Ordering::AcqRel) == 1 {
+        entry.file.set_len(entry.filesize)?;
+
+        if let Some(expected) = final_hash {
+            let actual = utils::whole_file_hash(&entry.file, entry.filesize)?;
+            if actual != expected {
+                stream_registry().lock().unwrap().remove(&entry.stream_id);
+                println!(
+                    " FAILED: hash mismatch after transfer; keeping {:?} for a future resume",
+                    entry.tmp_path
+                );
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Whole-file hash verification failed",
+                ));
+            }
+        }
+
+        fs::rename(&entry.tmp_path, &entry.dest_path)?;
+        remove_partial_state(&entry.dest_path);
+        stream_registry().lock().unwrap().remove(&entry.stream_id);
+        println!(" done!");
+    }
+
+    Ok(())
+}
+
+/// Recurses into `dir`, collecting every regular file found. The `--get`
+/// counterpart of `client::get_file_list`'s own directory walk, used to
+/// expand a requested directory when `--recursive` is set.
+fn scope_dir(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::<PathBuf>::new();
+
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            files.append(&mut scope_dir(&entry.path())?);
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+
+    Ok(files)
+}
+
+/// Serves a `--get` pull request: resolves each requested path, applying the
+/// same `--allow-dangerous-filepath` check a push destination gets and
+/// expanding directories when `--recursive` is set, then streams each
+/// matching file back over the Init/InitAck/Data/Copy exchange a push
+/// transfer uses - just with the roles reversed, so the server is now the
+/// sender and the client is the receiver offering a delta of its own copy.
+fn send_requested(
+    stream: &mut dyn transport::Stream,
+    enc: &mut Option<TeleportEnc>,
+    opt: &Opt,
+    data: &[u8],
+    ip: SocketAddr,
+) -> Result<(), Error> {
+    let mut req = TeleportGetRequest { paths: Vec::new() };
+    req.deserialize(data)?;
+
+    let mut files = Vec::<PathBuf>::new();
+    for requested in &req.paths {
+        let path = match resolve_path(requested, opt) {
+            Ok(p) => p,
+            Err(e) => {
+                println!("Refusing to send {:?} to {}: {}", requested, ip, e);
+                continue;
+            }
+        };
+
+        if path.is_dir() {
+            if opt.recursive {
+                match scope_dir(&path) {
+                    Ok(mut found) => files.append(&mut found),
+                    Err(e) => println!("Error reading directory {:?}: {}", path, e),
+                }
+            } else {
+                println!(
+                    "Skipping directory {:?} (use --recursive to send its contents)",
+                    path
+                );
+            }
+        } else if path.is_file() {
+            files.push(path);
+        } else {
+            println!("Requested path not found: {:?}", path);
+        }
+    }
+
+    let totalfiles = files.len() as u32;
+    if totalfiles == 0 {
+        // Let the client know there's nothing coming so it doesn't sit
+        // waiting for a file that will never arrive.
+        let mut header = TeleportInit::new(TeleportFeatures::NewFile);
+        header.totalfiles = 0;
+        utils::send_packet(stream, TeleportAction::Init, enc, None, header.serialize()?, 0)?;
+        return Ok(());
+    }
+
+    for (idx, path) in files.iter().enumerate() {
+        // Each file restarts its own Data chunk offsets at 0, so a fixed
+        // stream-cipher nonce would reuse the same (nonce, offset)
+        // keystream across files - draw a fresh one before every file.
+        if let Some(e) = enc.as_mut() {
+            e.regenerate_stream_nonce();
+        }
+        send_one_file(stream, &*enc, opt, path, idx as u32 + 1, totalfiles, ip)?;
+    }
+
+    Ok(())
+}
+
+/// Sends a single file requested via `--get`, playing the sender's role
+/// `client::send`/`client::send_delta` normally play in a push transfer.
+fn send_one_file(
+    stream: &mut dyn transport::Stream,
+    enc: &Option<TeleportEnc>,
+    opt: &Opt,
+    path: &Path,
+    filenum: u32,
+    totalfiles: u32,
+    ip: SocketAddr,
+) -> Result<(), Error> {
+    let file = File::open(path)?;
+    let meta = file.metadata()?;
+
+    let filename = if opt.keep_path {
+        path.to_str().unwrap().to_string()
+    } else {
+        path.file_name().unwrap().to_str().unwrap().to_string()
+    };
+
+    let mut features: u32 = 0;
+    if !opt.no_delta {
+        features |= TeleportFeatures::Delta as u32;
+    }
+    if opt.compress {
+        features |= TeleportFeatures::Compress as u32;
+    }
+
+    let mut header = TeleportInit::new(TeleportFeatures::NewFile);
+    header.filenum = filenum;
+    header.totalfiles = totalfiles;
+    header.chmod = meta.permissions().mode();
+    header.filesize = meta.len();
+    header.filename = filename.chars().collect();
+    header.features = features;
+    header.compress_threshold = opt.compress_threshold;
+    header.stream_id = utils::random_id(7);
+    // Hashed up front (rather than only at completion, like the trailing
+    // TeleportData::file_hash) so a resumed --get request's receiver can
+    // tell this is really a continuation of the same file. This is a
+    // separate, cheap whole-file read (not the chunked delta hash), so it's
+    // computed unconditionally - --no-delta must still skip calc_delta_hash,
+    // but a resume with no hash at all to validate against isn't safe to
+    // trust, so it's never skipped here.
+    header.source_hash = Some(utils::whole_file_hash(&file, meta.len())?);
+
+    println!(
+        "Sending file {}/{}: {:?} (to {})",
+        filenum, totalfiles, filename, ip
+    );
+    utils::send_packet(stream, TeleportAction::Init, enc, None, header.serialize()?, 0)?;
+
+    let packet = utils::recv_packet(stream, enc, None)?;
+    let mut ack = TeleportInitAck::new(TeleportStatus::Proceed);
+    ack.deserialize(&packet.data)?;
+
+    match ack.status.try_into().unwrap() {
+        TeleportStatus::NoOverwrite => {
+            println!(" => client already has {:?}, skipping", filename);
+            return Ok(());
+        }
+        TeleportStatus::NoPermission => {
+            println!(" => client refused to write {:?}", filename);
+            return Ok(());
+        }
+        _ => (),
+    }
+
+    // Calculate our own delta hash on a helper thread. It is started only
+    // after the client's ack has arrived, and is joined right away when that
+    // ack offers a delta of the client's existing copy.
+    let thread_file = file.try_clone()?;
+    let handle = if !opt.no_delta {
+        Some(thread::spawn(move || {
+            utils::calc_delta_hash(&thread_file).unwrap()
+        }))
+    } else {
+        None
+    };
+
+    let csum_recv = ack.delta.as_ref().map(|d| d.hash);
+    let file_delta = if utils::check_feature(&ack.features, TeleportFeatures::Overwrite) {
+        handle.map(|h| h.join().expect("calc_delta_hash panicked"))
+    } else {
+        None
+    };
+
+    let compress_threshold = if opt.compress && utils::check_feature(&ack.features, TeleportFeatures::Compress) {
+        Some(opt.compress_threshold)
+    } else {
+        None
+    };
+
+    let resume_offset = ack.resume_offset.unwrap_or(0);
+
+    if csum_recv.is_some()
+        && file_delta.is_some()
+        && file_delta.as_ref().unwrap().hash == csum_recv.unwrap()
+    {
+        send_data_complete(stream, enc, compress_threshold, file, Some(csum_recv.unwrap()))?;
+    } else {
+        send(
+            stream,
+            file,
+            &header,
+            enc,
+            compress_threshold,
+            ack.delta,
+            file_delta,
+            resume_offset,
+        )?;
     }
 
+    println!(" done!");
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume_rejected_without_sidecar() {
+        assert!(!resume_matches(None, Some(42)));
+    }
+
+    #[test]
+    fn resume_rejected_without_sender_hash() {
+        let state = PartialState { source_hash: None, received: 100 };
+        assert!(!resume_matches(Some(&state), None));
+        assert!(!resume_matches(Some(&state), Some(42)));
+    }
+
+    #[test]
+    fn resume_rejected_on_hash_mismatch() {
+        let state = PartialState { source_hash: Some(1), received: 100 };
+        assert!(!resume_matches(Some(&state), Some(2)));
+    }
+
+    #[test]
+    fn resume_accepted_on_matching_hash() {
+        let state = PartialState { source_hash: Some(42), received: 100 };
+        assert!(resume_matches(Some(&state), Some(42)));
+    }
+
+    #[test]
+    fn partial_state_round_trips_through_disk() {
+        let dir = std::env::temp_dir().join(format!("teleport-test-{}", utils::random_id(8)));
+        let dest_path = dir.join("dest.bin");
+        fs::create_dir_all(&dir).unwrap();
+
+        assert!(read_partial_state(&dest_path).is_none());
+
+        let state = PartialState { source_hash: Some(7), received: 1024 };
+        write_partial_state(&dest_path, &state).unwrap();
+
+        let read_back = read_partial_state(&dest_path).unwrap();
+        assert_eq!(read_back.source_hash, Some(7));
+        assert_eq!(read_back.received, 1024);
+
+        remove_partial_state(&dest_path);
+        assert!(read_partial_state(&dest_path).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+use crate::Opt;
+use std::fmt;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+
+mod udp;
+
+/// The socket abstraction `client`/`server` talk to: `teleport`'s framing
+/// and `utils::send_packet`/`recv_packet` only need `Read`/`Write` plus the
+/// two extras `TcpStream` already has inherently (`peek`, `peer_addr`), so
+/// this is the full surface a transport has to provide.
+pub trait Stream: Read + Write + Send {
+    /// Looks at up to the next `buf.len()` bytes without consuming them,
+    /// returning how many were copied. The TCP implementation may return
+    /// fewer than requested (`utils::recv_packet` calls it again until the
+    /// whole header is there); the UDP one blocks until that many are
+    /// available. Lets `utils::recv_packet` learn a packet's length before
+    /// committing to reading it off the wire.
+    fn peek(&mut self, buf: &mut [u8]) -> Result<usize, Error>;
+    fn peer_addr(&self) -> Result<SocketAddr, Error>;
+
+    /// A duplicated handle onto the same underlying `TcpStream`, for
+    /// `client::send_parallel`'s worker-0-reuses-the-primary-connection
+    /// trick. `--streams` stays a TCP-only feature, so every other
+    /// transport just declines.
+    fn try_clone_tcp(&self) -> Option<TcpStream> {
+        None
+    }
+}
+
+impl Stream for TcpStream {
+    fn peek(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        TcpStream::peek(self, buf)
+    }
+
+    fn peer_addr(&self) -> Result<SocketAddr, Error> {
+        TcpStream::peer_addr(self)
+    }
+
+    fn try_clone_tcp(&self) -> Option<TcpStream> {
+        self.try_clone().ok()
+    }
+}
+
+/// Which socket type to carry a transfer over, selected with `--transport`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+impl FromStr for Transport {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.to_lowercase().as_str() {
+            "tcp" => Ok(Transport::Tcp),
+            "udp" => Ok(Transport::Udp),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown transport: {} (expected 'tcp' or 'udp')", s),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Transport::Tcp => write!(f, "tcp"),
+            Transport::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+/// Dials `addr` over the given `transport`. Used for the primary,
+/// handshaking connection (including the `--ssh` bootstrap) and for each of
+/// `client::send_files_parallel`'s whole-file connections;
+/// `client::send_parallel`'s byte-range workers dial TCP directly instead.
+pub fn connect(addr: SocketAddr, transport: Transport) -> Result<Box<dyn Stream>, Error> {
+    match transport {
+        Transport::Tcp => Ok(Box::new(TcpStream::connect(addr)?)),
+        Transport::Udp => Ok(Box::new(udp::UdpStream::connect(addr)?)),
+    }
+}
+
+/// A listening socket that hands back individual connections as `Stream`s,
+/// regardless of which transport is backing them.
+pub enum Listener {
+    Tcp(TcpListener),
+    Udp(udp::UdpListener),
+}
+
+impl Listener {
+    pub fn bind(opt: &Opt) -> Result<Self, Error> {
+        let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, opt.port));
+        match opt.transport {
+            Transport::Tcp => Ok(Listener::Tcp(TcpListener::bind(addr)?)),
+            Transport::Udp => Ok(Listener::Udp(udp::UdpListener::bind(addr)?)),
+        }
+    }
+
+    /// Blocks for the next incoming connection. Mirrors
+    /// `TcpListener::incoming().next()`, just transport-agnostic.
+    pub fn accept(&self) -> Result<Box<dyn Stream>, Error> {
+        match self {
+            Listener::Tcp(l) => {
+                let (s, _) = l.accept()?;
+                Ok(Box::new(s))
+            }
+            Listener::Udp(l) => Ok(Box::new(l.accept()?)),
+        }
+    }
+
+    /// The address actually bound to, so a caller that bound `--port 0` can
+    /// learn which ephemeral port the OS picked (used by `server::run`'s
+    /// `--one-shot` mode to announce a port for `--ssh` bootstrap clients).
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        match self {
+            Listener::Tcp(l) => l.local_addr(),
+            Listener::Udp(l) => l.local_addr(),
+        }
+    }
+}
@@ -0,0 +1,544 @@
+use crate::transport::Stream;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Payload bytes per datagram, chosen to stay under a typical 1500 byte MTU
+/// once our 24 byte header and IP/UDP headers are added.
+const MSS: usize = 1200;
+const HEADER_LEN: usize = 24;
+const MIN_CWND: f64 = MSS as f64;
+const INIT_CWND: f64 = (MSS * 4) as f64;
+/// LEDBAT's target queuing delay: the sender backs its window off once the
+/// one-way delay it's causing the peer rises above this, well before a
+/// queue actually overflows and a loss-based scheme would notice.
+const TARGET_DELAY_US: i64 = 100_000;
+const GAIN: f64 = 1.0;
+const MIN_RTO: Duration = Duration::from_millis(300);
+const TICK: Duration = Duration::from_millis(10);
+
+/// A single reliable-UDP datagram: our own minimal seq/ack/SACK framing,
+/// wrapping whatever bytes `Write::write` handed us. Lower-level than (and
+/// unrelated to) `teleport::TeleportHeader`, which rides on top of this as
+/// ordinary stream bytes.
+struct DatagramHeader {
+    /// Sequence number of `payload`; meaningless (but still present) on a
+    /// pure-ack datagram with an empty payload.
+    seq: u32,
+    /// Next seq this side expects, i.e. a cumulative ack of the peer's data.
+    ack: u32,
+    /// Bitmap of `ack+1 ..= ack+32`, for acking out-of-order arrivals so the
+    /// peer doesn't have to wait out a timeout to retransmit just the gap.
+    sack: u32,
+    /// This side's local clock when the datagram was sent, echoed back by
+    /// the peer's next datagram's `delay_us` so we learn the one-way delay
+    /// we're causing it without needing synchronized clocks.
+    send_ts_us: u64,
+    /// One-way delay sample (recv time minus the remote's `send_ts_us`) the
+    /// peer most recently measured on data from us, in other words how
+    /// congested our send direction looks from its side.
+    delay_us: u32,
+}
+
+impl DatagramHeader {
+    fn serialize(&self, payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + payload.len());
+        out.write_u32::<LittleEndian>(self.seq).unwrap();
+        out.write_u32::<LittleEndian>(self.ack).unwrap();
+        out.write_u32::<LittleEndian>(self.sack).unwrap();
+        out.write_u64::<LittleEndian>(self.send_ts_us).unwrap();
+        out.write_u32::<LittleEndian>(self.delay_us).unwrap();
+        out.extend_from_slice(payload);
+        out
+    }
+
+    fn deserialize(mut buf: &[u8]) -> Result<(Self, Vec<u8>), Error> {
+        if buf.len() < HEADER_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, "Short udp datagram"));
+        }
+        let header = DatagramHeader {
+            seq: buf.read_u32::<LittleEndian>()?,
+            ack: buf.read_u32::<LittleEndian>()?,
+            sack: buf.read_u32::<LittleEndian>()?,
+            send_ts_us: buf.read_u64::<LittleEndian>()?,
+            delay_us: buf.read_u32::<LittleEndian>()?,
+        };
+        Ok((header, buf.to_vec()))
+    }
+}
+
+fn now_us() -> u64 {
+    static START: std::sync::OnceLock<Instant> = std::sync::OnceLock::new();
+    let start = *START.get_or_init(Instant::now);
+    start.elapsed().as_micros() as u64
+}
+
+struct UnackedPacket {
+    payload: Vec<u8>,
+    sent_at: Instant,
+    tries: u32,
+}
+
+struct SendState {
+    pending: VecDeque<(u32, Vec<u8>)>,
+    unacked: BTreeMap<u32, UnackedPacket>,
+    next_seq: u32,
+    cwnd: f64,
+    base_delay_us: i64,
+    rto: Duration,
+}
+
+struct RecvState {
+    /// Next seq we haven't yet delivered to `read()`.
+    next_seq: u32,
+    /// Out-of-order payloads waiting on a gap to close.
+    reordered: BTreeMap<u32, Vec<u8>>,
+    /// In-order bytes `Read::read` drains from.
+    ready: VecDeque<u8>,
+    /// Most recent one-way delay we measured on incoming data, echoed back
+    /// to the peer so it can run its own congestion control.
+    last_delay_us: u32,
+}
+
+/// A reliable, ordered byte stream over UDP: per-packet sequence numbers,
+/// cumulative+SACK acknowledgement, retransmission on timeout, and a LEDBAT
+/// style congestion window that backs off on rising one-way delay rather
+/// than waiting for outright loss. Implements `Read + Write` so `teleport`'s
+/// framing code above it doesn't need to know or care.
+pub struct UdpStream {
+    send: Arc<Mutex<SendState>>,
+    send_cv: Arc<Condvar>,
+    recv: Arc<Mutex<RecvState>>,
+    recv_cv: Arc<Condvar>,
+    peer: SocketAddr,
+    closed: Arc<AtomicBool>,
+    engine: Option<thread::JoinHandle<()>>,
+    /// `connect`'s own dedicated reader thread, blocked on `sock.recv`.
+    /// `None` for `accepted` streams, whose socket is demultiplexed and read
+    /// by `UdpListener`'s own thread instead.
+    reader: Option<thread::JoinHandle<()>>,
+}
+
+impl UdpStream {
+    fn new(sock: Arc<UdpSocket>, peer: SocketAddr, inbox: Receiver<Vec<u8>>) -> Self {
+        let send = Arc::new(Mutex::new(SendState {
+            pending: VecDeque::new(),
+            unacked: BTreeMap::new(),
+            next_seq: 0,
+            cwnd: INIT_CWND,
+            base_delay_us: i64::MAX,
+            rto: MIN_RTO,
+        }));
+        let recv = Arc::new(Mutex::new(RecvState {
+            next_seq: 0,
+            reordered: BTreeMap::new(),
+            ready: VecDeque::new(),
+            last_delay_us: 0,
+        }));
+        let send_cv = Arc::new(Condvar::new());
+        let recv_cv = Arc::new(Condvar::new());
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let engine = thread::spawn({
+            let send = Arc::clone(&send);
+            let send_cv = Arc::clone(&send_cv);
+            let recv = Arc::clone(&recv);
+            let recv_cv = Arc::clone(&recv_cv);
+            let closed = Arc::clone(&closed);
+            move || engine_loop(sock, peer, inbox, send, send_cv, recv, recv_cv, closed)
+        });
+
+        UdpStream {
+            send,
+            send_cv,
+            recv,
+            recv_cv,
+            peer,
+            closed,
+            engine: Some(engine),
+            reader: None,
+        }
+    }
+
+    /// Binds an ephemeral local port and dials `peer`. Used client-side,
+    /// where this stream owns the socket outright.
+    pub fn connect(peer: SocketAddr) -> Result<Self, Error> {
+        let sock = UdpSocket::bind(("0.0.0.0", 0))?;
+        // Fixes the kernel's idea of our peer so plain `recv` works and
+        // datagrams from anyone else are dropped before we ever see them.
+        sock.connect(peer)?;
+        let sock = Arc::new(sock);
+        let (tx, rx) = mpsc::channel();
+        let mut stream = UdpStream::new(Arc::clone(&sock), peer, rx);
+        stream.reader = Some(spawn_reader(sock, tx, Arc::clone(&stream.closed)));
+        Ok(stream)
+    }
+
+    /// Wraps a socket shared with other peers (server-side, where one bound
+    /// port demultiplexes many connections) plus the channel `UdpListener`
+    /// already arranged to feed this peer's datagrams.
+    fn accepted(sock: Arc<UdpSocket>, peer: SocketAddr, inbox: Receiver<Vec<u8>>) -> Self {
+        UdpStream::new(sock, peer, inbox)
+    }
+}
+
+/// Reads datagrams off `sock` until `closed` is set. A read timeout bounds
+/// how long a blocking `recv` can hold the thread hostage so it actually
+/// notices `closed` and exits, rather than blocking forever on a socket
+/// nothing will ever send to again once the peer is gone - `Drop` joins this
+/// thread and would otherwise hang or leak it.
+fn spawn_reader(sock: Arc<UdpSocket>, tx: Sender<Vec<u8>>, closed: Arc<AtomicBool>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let _ = sock.set_read_timeout(Some(TICK));
+        let mut buf = [0u8; 2048];
+        while !closed.load(Ordering::Acquire) {
+            match sock.recv(&mut buf) {
+                Ok(n) => {
+                    if tx.send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => continue,
+                Err(_) => break,
+            }
+        }
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn engine_loop(
+    sock: Arc<UdpSocket>,
+    peer: SocketAddr,
+    inbox: Receiver<Vec<u8>>,
+    send: Arc<Mutex<SendState>>,
+    send_cv: Arc<Condvar>,
+    recv: Arc<Mutex<RecvState>>,
+    recv_cv: Arc<Condvar>,
+    closed: Arc<AtomicBool>,
+) {
+    let mut want_ack = false;
+
+    while !closed.load(Ordering::Acquire) {
+        // Block for the first datagram of the tick, then drain whatever
+        // else already queued up behind it instead of handling one datagram
+        // per `TICK` — at a 10ms tick that would otherwise cap throughput
+        // at a few hundred packets a second.
+        let mut incoming: Vec<Vec<u8>> = match inbox.recv_timeout(TICK) {
+            Ok(datagram) => vec![datagram],
+            Err(_) => Vec::new(),
+        };
+        while let Ok(datagram) = inbox.try_recv() {
+            incoming.push(datagram);
+        }
+
+        for datagram in incoming {
+            if let Ok((header, payload)) = DatagramHeader::deserialize(&datagram) {
+                handle_incoming(&header, payload, &send, &send_cv, &recv, &recv_cv);
+                want_ack = true;
+            }
+        }
+
+        let sent_any = pump(&sock, peer, &send, &recv, want_ack);
+        want_ack = want_ack && !sent_any;
+    }
+}
+
+/// Applies an inbound datagram's ack/SACK/delay sample to our send side and,
+/// if it carried data, folds its payload into the receive side's reorder
+/// buffer.
+fn handle_incoming(
+    header: &DatagramHeader,
+    payload: Vec<u8>,
+    send: &Arc<Mutex<SendState>>,
+    send_cv: &Arc<Condvar>,
+    recv: &Arc<Mutex<RecvState>>,
+    recv_cv: &Arc<Condvar>,
+) {
+    {
+        let mut s = send.lock().unwrap();
+        let mut acked_bytes = 0usize;
+        let acked: Vec<u32> = s
+            .unacked
+            .keys()
+            .filter(|&&seq| {
+                // Covered by the cumulative ack: `seq - ack` wraps to a huge
+                // value iff `seq < ack`.
+                let behind_ack = seq.wrapping_sub(header.ack) >= u32::MAX / 2;
+                // Or individually selective-acked: bit `seq - ack - 1` of
+                // the map covering `ack+1 ..= ack+32`.
+                let sacked = seq > header.ack
+                    && seq <= header.ack.wrapping_add(32)
+                    && header.sack & (1 << (seq - header.ack - 1)) != 0;
+                behind_ack || sacked
+            })
+            .copied()
+            .collect();
+        for seq in acked {
+            if let Some(p) = s.unacked.remove(&seq) {
+                acked_bytes += p.payload.len() + HEADER_LEN;
+                let rtt = p.sent_at.elapsed();
+                s.rto = std::cmp::max(MIN_RTO, rtt + rtt / 2);
+            }
+        }
+
+        if acked_bytes > 0 {
+            let delay = header.delay_us as i64;
+            if delay > 0 {
+                s.base_delay_us = std::cmp::min(s.base_delay_us, delay);
+                let base = if s.base_delay_us == i64::MAX { delay } else { s.base_delay_us };
+                let queuing_delay = (delay - base).max(0);
+                let off_target = (TARGET_DELAY_US - queuing_delay) as f64 / TARGET_DELAY_US as f64;
+                s.cwnd = (s.cwnd + GAIN * off_target * acked_bytes as f64 / s.cwnd).max(MIN_CWND);
+            }
+            send_cv.notify_all();
+        }
+    }
+
+    if payload.is_empty() {
+        return;
+    }
+
+    let mut r = recv.lock().unwrap();
+    r.last_delay_us = ((now_us() as i64 - header.send_ts_us as i64).max(0)) as u32;
+
+    if header.seq == r.next_seq {
+        r.next_seq = r.next_seq.wrapping_add(1);
+        r.ready.extend(payload);
+        while let Some(next) = r.reordered.remove(&r.next_seq) {
+            r.next_seq = r.next_seq.wrapping_add(1);
+            r.ready.extend(next);
+        }
+    } else if header.seq.wrapping_sub(r.next_seq) < 32 {
+        r.reordered.insert(header.seq, payload);
+    }
+    recv_cv.notify_all();
+}
+
+/// Sends whatever the congestion window allows, retransmits anything that's
+/// timed out, and otherwise sends a bare ack so the peer's window and delay
+/// estimate keep moving even while we have no data of our own. Returns
+/// whether anything was actually put on the wire.
+fn pump(sock: &UdpSocket, peer: SocketAddr, send: &Arc<Mutex<SendState>>, recv: &Arc<Mutex<RecvState>>, want_ack: bool) -> bool {
+    let (ack, sack, delay_us) = {
+        let r = recv.lock().unwrap();
+        let mut sack = 0u32;
+        for seq in r.reordered.keys() {
+            let diff = seq.wrapping_sub(r.next_seq);
+            if diff >= 1 && diff <= 32 {
+                sack |= 1 << (diff - 1);
+            }
+        }
+        (r.next_seq, sack, r.last_delay_us)
+    };
+
+    let mut s = send.lock().unwrap();
+    let rto = s.rto;
+
+    let mut retransmitted = false;
+    // Back off the effective timeout per packet (capped) so a run of losses
+    // on one segment doesn't spin the retransmitter hot while everything
+    // else is still waiting out a normal RTO.
+    let timed_out: Vec<u32> = s
+        .unacked
+        .iter()
+        .filter(|(_, p)| p.sent_at.elapsed() > rto * (1u32 << p.tries.min(6)))
+        .map(|(&seq, _)| seq)
+        .collect();
+    for seq in timed_out {
+        if let Some(p) = s.unacked.get_mut(&seq) {
+            let header = DatagramHeader {
+                seq,
+                ack,
+                sack,
+                send_ts_us: now_us(),
+                delay_us,
+            };
+            let _ = sock.send_to(&header.serialize(&p.payload), peer);
+            p.sent_at = Instant::now();
+            p.tries += 1;
+            retransmitted = true;
+        }
+    }
+    // A timeout is still evidence of real congestion (or loss), so react
+    // the same way TCP would alongside the delay-based signal above.
+    if retransmitted {
+        s.cwnd = (s.cwnd / 2.0).max(MIN_CWND);
+    }
+
+    let mut sent_data = false;
+    loop {
+        let in_flight: usize = s.unacked.values().map(|p| p.payload.len() + HEADER_LEN).sum();
+        if in_flight >= s.cwnd as usize {
+            break;
+        }
+        let (seq, payload) = match s.pending.pop_front() {
+            Some(v) => v,
+            None => break,
+        };
+        let header = DatagramHeader {
+            seq,
+            ack,
+            sack,
+            send_ts_us: now_us(),
+            delay_us,
+        };
+        let _ = sock.send_to(&header.serialize(&payload), peer);
+        s.unacked.insert(
+            seq,
+            UnackedPacket {
+                payload,
+                sent_at: Instant::now(),
+                tries: 0,
+            },
+        );
+        sent_data = true;
+    }
+
+    if !sent_data && !retransmitted && want_ack {
+        let header = DatagramHeader {
+            seq: 0,
+            ack,
+            sack,
+            send_ts_us: now_us(),
+            delay_us,
+        };
+        let _ = sock.send_to(&header.serialize(&[]), peer);
+    }
+
+    sent_data || retransmitted
+}
+
+impl Read for UdpStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut r = self.recv.lock().unwrap();
+        while r.ready.is_empty() {
+            r = self.recv_cv.wait_timeout(r, TICK).unwrap().0;
+        }
+        let n = std::cmp::min(buf.len(), r.ready.len());
+        for slot in buf.iter_mut().take(n) {
+            *slot = r.ready.pop_front().unwrap();
+        }
+        Ok(n)
+    }
+}
+
+impl Write for UdpStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let mut s = self.send.lock().unwrap();
+        for chunk in buf.chunks(MSS) {
+            let seq = s.next_seq;
+            s.next_seq = s.next_seq.wrapping_add(1);
+            s.pending.push_back((seq, chunk.to_vec()));
+        }
+        drop(s);
+        self.send_cv.notify_all();
+        Ok(buf.len())
+    }
+
+    /// Blocks until every byte handed to `write` so far has been acked,
+    /// since `utils::send_packet` relies on `flush` meaning "on the wire and
+    /// durable" the same way it would for a `TcpStream`.
+    fn flush(&mut self) -> Result<(), Error> {
+        let mut s = self.send.lock().unwrap();
+        loop {
+            if s.pending.is_empty() && s.unacked.is_empty() {
+                return Ok(());
+            }
+            s = self.send_cv.wait_timeout(s, TICK).unwrap().0;
+        }
+    }
+}
+
+impl Stream for UdpStream {
+    fn peek(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut r = self.recv.lock().unwrap();
+        while r.ready.len() < buf.len() {
+            r = self.recv_cv.wait_timeout(r, TICK).unwrap().0;
+        }
+        for (slot, byte) in buf.iter_mut().zip(r.ready.iter()) {
+            *slot = *byte;
+        }
+        Ok(buf.len())
+    }
+
+    fn peer_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.peer)
+    }
+}
+
+impl Drop for UdpStream {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Release);
+        if let Some(h) = self.engine.take() {
+            let _ = h.join();
+        }
+        // Bounded by spawn_reader's own read timeout, so this won't hang.
+        if let Some(h) = self.reader.take() {
+            let _ = h.join();
+        }
+    }
+}
+
+/// Demultiplexes one bound UDP socket across many peers: a single reader
+/// thread classifies each datagram by source address, handing it to the
+/// matching `UdpStream`'s channel or, for an address seen for the first
+/// time, spinning up a new stream and surfacing it via `accept`.
+pub struct UdpListener {
+    accept_rx: Mutex<Receiver<UdpStream>>,
+    local_addr: SocketAddr,
+}
+
+impl UdpListener {
+    pub fn bind(addr: SocketAddr) -> Result<Self, Error> {
+        let demux_sock = Arc::new(UdpSocket::bind(addr)?);
+        let local_addr = demux_sock.local_addr()?;
+        let (accept_tx, accept_rx) = mpsc::channel::<UdpStream>();
+
+        thread::spawn(move || {
+            let mut peers: HashMap<SocketAddr, Sender<Vec<u8>>> = HashMap::new();
+            let mut buf = [0u8; 2048];
+            loop {
+                let (n, from) = match demux_sock.recv_from(&mut buf) {
+                    Ok(v) => v,
+                    Err(_) => break,
+                };
+                let datagram = buf[..n].to_vec();
+
+                if let Some(tx) = peers.get(&from) {
+                    if tx.send(datagram).is_err() {
+                        peers.remove(&from);
+                    }
+                    continue;
+                }
+
+                let (tx, rx) = mpsc::channel();
+                let _ = tx.send(datagram);
+                let stream = UdpStream::accepted(Arc::clone(&demux_sock), from, rx);
+                if accept_tx.send(stream).is_err() {
+                    break;
+                }
+                peers.insert(from, tx);
+            }
+        });
+
+        Ok(UdpListener { accept_rx: Mutex::new(accept_rx), local_addr })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.local_addr)
+    }
+
+    pub fn accept(&self) -> Result<UdpStream, Error> {
+        self.accept_rx
+            .lock()
+            .unwrap()
+            .recv()
+            .map_err(|_| Error::new(ErrorKind::BrokenPipe, "udp listener closed"))
+    }
+}
@@ -1,10 +1,14 @@
 use crate::teleport::TeleportInit;
-use crate::teleport::{TeleportAction, TeleportEnc, TeleportFeatures, TeleportHeader};
+use crate::teleport::{TeleportAction, TeleportCopy, TeleportEnc, TeleportFeatures, TeleportHeader};
+use crate::transport::Stream;
 use crate::*;
-use byteorder::{LittleEndian, ReadBytesExt};
-use rand::prelude::*;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::write::ZlibEncoder;
+use flate2::read::ZlibDecoder;
+use flate2::Compression;
 use rand::{distributions::Alphanumeric, Rng};
 use std::hash::Hasher;
+use std::os::unix::fs::FileExt;
 use xxhash_rust::xxh3;
 
 struct SizeUnit {
@@ -61,27 +65,62 @@ fn identify_unit(mut value: f64) -> SizeUnit {
     }
 }
 
+/// Compresses `data` with zlib when it exceeds `threshold` bytes, framing the
+/// result with a 4-byte little-endian prefix giving the original length (0
+/// meaning "stored, not compressed").
+fn compress_payload(data: &[u8], threshold: u32) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::<u8>::new();
+    if data.len() > threshold as usize {
+        let mut compressed = Vec::<u8>::new();
+        let mut enc = ZlibEncoder::new(&mut compressed, Compression::default());
+        enc.write_all(data)?;
+        enc.finish()?;
+
+        out.write_u32::<LittleEndian>(data.len() as u32)?;
+        out.extend_from_slice(&compressed);
+    } else {
+        out.write_u32::<LittleEndian>(0)?;
+        out.extend_from_slice(data);
+    }
+    Ok(out)
+}
+
+/// Reverses `compress_payload`, decompressing when the prefix is nonzero.
+fn decompress_payload(buf: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut cur: &[u8] = buf;
+    let orig_len = cur.read_u32::<LittleEndian>()?;
+    if orig_len == 0 {
+        return Ok(cur.to_vec());
+    }
+
+    let mut out = Vec::<u8>::with_capacity(orig_len as usize);
+    let mut dec = ZlibDecoder::new(cur);
+    dec.read_to_end(&mut out)?;
+    Ok(out)
+}
+
 pub fn send_packet(
-    sock: &mut TcpStream,
+    sock: &mut dyn Stream,
     action: TeleportAction,
     enc: &Option<TeleportEnc>,
+    compress_threshold: Option<u32>,
     data: Vec<u8>,
+    offset: u64,
 ) -> Result<(), Error> {
     let mut header = TeleportHeader::new(action);
 
+    // Compress before encrypting, if a threshold was negotiated
+    let data = match compress_threshold {
+        Some(threshold) => compress_payload(&data, threshold)?,
+        None => data,
+    };
+
     // If encryption is enabled
     if let Some(ctx) = enc {
-        // Use random IV
-        let mut rng = StdRng::from_entropy();
-        let mut iv: [u8; 12] = [0; 12];
-        rng.fill(&mut iv);
-
         header.action |= TeleportAction::Encrypted as u8;
 
-        // Encrypt the data array
-        header.data = ctx.encrypt(&iv, &data)?;
-
-        // Set the IV in the header
+        let (iv, ciphertext) = ctx.encrypt(offset, &data)?;
+        header.data = ciphertext;
         header.iv = Some(iv);
     } else {
         header.data = data;
@@ -98,8 +137,9 @@ pub fn send_packet(
 }
 
 pub fn recv_packet(
-    sock: &mut TcpStream,
+    sock: &mut dyn Stream,
     dec: &Option<TeleportEnc>,
+    compress_threshold: Option<u32>,
 ) -> Result<TeleportHeader, Error> {
     let mut initbuf: [u8; 13] = [0; 13];
     loop {
@@ -140,6 +180,10 @@ pub fn recv_packet(
         }
     }
 
+    if compress_threshold.is_some() {
+        out.data = decompress_payload(&out.data)?;
+    }
+
     Ok(out)
 }
 
@@ -191,6 +235,7 @@ pub fn calc_delta_hash(mut file: &File) -> Result<teleport::TeleportDelta, Error
     buf.resize(gen_chunk_size(meta.len()), 0);
     let mut whole_hasher = xxh3::Xxh3::new();
     let mut chunk_hash = Vec::<u64>::new();
+    let mut weak_hash = Vec::<u32>::new();
 
     loop {
         let mut hasher = xxh3::Xxh3::new();
@@ -203,10 +248,11 @@ pub fn calc_delta_hash(mut file: &File) -> Result<teleport::TeleportDelta, Error
             break;
         }
 
-        hasher.write(&buf);
+        hasher.write(&buf[..len]);
         chunk_hash.push(hasher.finish());
+        weak_hash.push(delta::RollingChecksum::new(&buf[..len]).digest());
 
-        whole_hasher.write(&buf);
+        whole_hasher.write(&buf[..len]);
     }
 
     let mut out = teleport::TeleportDelta::new();
@@ -214,16 +260,66 @@ pub fn calc_delta_hash(mut file: &File) -> Result<teleport::TeleportDelta, Error
     out.chunk_size = buf.len().try_into().unwrap();
     out.hash = whole_hasher.finish();
     out.chunk_hash = chunk_hash;
+    out.weak_hash = weak_hash;
 
     file.seek(SeekFrom::Start(0))?;
 
     Ok(out)
 }
 
-pub(crate) fn random_id() -> String {
+/// Computes the xxh3 hash of the first `len` bytes of `file` via positioned
+/// reads, so it can be called on a `StreamEntry`'s shared file handle
+/// without disturbing any other reader/writer's use of it. Used by the
+/// server to verify a resumed or delta-reconstructed file matches the
+/// sender's copy before renaming its `.part` into place.
+pub fn whole_file_hash(file: &File, len: u64) -> Result<u64, Error> {
+    let mut hasher = xxh3::Xxh3::new();
+    let mut buf = vec![0u8; 1 << 20];
+    let mut offset = 0u64;
+    while offset < len {
+        let want = std::cmp::min(buf.len() as u64, len - offset) as usize;
+        let n = file.read_at(&mut buf[..want], offset)?;
+        if n == 0 {
+            break;
+        }
+        hasher.write(&buf[..n]);
+        offset += n as u64;
+    }
+    Ok(hasher.finish())
+}
+
+/// Services a `TeleportCopy` instruction by reading `copy.len` bytes out of
+/// `source` at `copy.block_index * chunk_size` and writing them into `dest`
+/// at `copy.dest_offset`. A `TeleportCopy` arrives over the wire with no
+/// validation of its own, so this rejects a `len` that doesn't match the
+/// block size we ourselves advertised (`compute_ops` only ever emits
+/// whole-block copies) and a `read_at` that comes up short of a full block,
+/// rather than silently writing a zero-padded block_index/len a hostile or
+/// malformed peer ran past the end of `source`.
+pub(crate) fn apply_copy(source: &File, chunk_size: u32, copy: &TeleportCopy, dest: &File) -> Result<(), Error> {
+    if copy.len != chunk_size {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Copy instruction length does not match the negotiated block size",
+        ));
+    }
+
+    let mut buf = vec![0u8; copy.len as usize];
+    let n = source.read_at(&mut buf, copy.block_index as u64 * chunk_size as u64)?;
+    if n != buf.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Copy instruction's block_index runs past the end of the source file",
+        ));
+    }
+
+    dest.write_at(&buf, copy.dest_offset)
+}
+
+pub(crate) fn random_id(len: usize) -> String {
     rand::thread_rng()
         .sample_iter(&Alphanumeric)
-        .take(7)
+        .take(len)
         .map(char::from)
         .collect()
 }